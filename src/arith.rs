@@ -0,0 +1,511 @@
+use crate::variables::{is_name, Variables};
+use thiserror::Error;
+
+/// How deep variables holding expressions may refer to one another.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ArithError {
+    #[error("division by 0")]
+    DivisionByZero,
+    #[error("integer overflow")]
+    Overflow,
+    #[error("exponent less than 0")]
+    NegativeExponent,
+    #[error("syntax error: operand expected (error token is \"{0}\")")]
+    OperandExpected(String),
+    #[error("syntax error in expression (error token is \"{0}\")")]
+    Syntax(String),
+    #[error("invalid number")]
+    InvalidNumber,
+    #[error("attempted assignment to non-variable")]
+    NotAVariable,
+    #[error("expression recursion level exceeded")]
+    TooDeep,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Num(String),
+    Name(String),
+    Op(&'static str),
+}
+
+/// Operators, longest first.
+const OPS: &[&str] = &[
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=", "/=", "%=",
+    "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~", "?", ":", "=", "(", ")", ",",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Tok>, ArithError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            // `#` allows numbers written as base#digits
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '#') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            if c.is_ascii_digit() {
+                tokens.push(Tok::Num(text));
+            } else {
+                tokens.push(Tok::Name(text));
+            }
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            match OPS.iter().find(|op| rest.starts_with(**op)) {
+                Some(op) => {
+                    tokens.push(Tok::Op(op));
+                    i += op.len();
+                }
+                None => return Err(ArithError::Syntax(rest)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Result<i64, ArithError> {
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        let base: u32 = base.parse().map_err(|_| ArithError::InvalidNumber)?;
+        if !(2..=36).contains(&base) {
+            return Err(ArithError::InvalidNumber);
+        }
+        (base, digits)
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, hex)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+    if digits.is_empty() {
+        return Err(ArithError::InvalidNumber);
+    }
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = c.to_digit(base).ok_or(ArithError::InvalidNumber)?;
+        value = value
+            .checked_mul(base as i64)
+            .and_then(|v| v.checked_add(digit as i64))
+            .ok_or(ArithError::Overflow)?;
+    }
+    Ok(value)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `name = value` or a compound assignment such as `name += value`
+    Assign(String, Option<&'static str>, Box<Expr>),
+    /// `++name`, `name--` and friends
+    Step { name: String, delta: i64, prefix: bool },
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+/// Binary operators from lowest to highest precedence. All are left
+/// associative except `**`, which is handled separately.
+const BINARY_LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser {
+    tokens: Vec<Tok>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Tok::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn error_token(&self) -> String {
+        self.tokens[self.pos.min(self.tokens.len())..]
+            .iter()
+            .map(|tok| match tok {
+                Tok::Num(s) | Tok::Name(s) => s.to_string(),
+                Tok::Op(op) => op.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), ArithError> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(ArithError::Syntax(self.error_token()))
+        }
+    }
+
+    fn comma(&mut self) -> Result<Expr, ArithError> {
+        let mut expr = self.assignment()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            let rhs = self.assignment()?;
+            expr = Expr::Binary(",", Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, ArithError> {
+        if let (Some(Tok::Name(name)), Some(Tok::Op(op))) = (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            let compound = match *op {
+                "=" => Some(None),
+                "+=" | "-=" | "*=" | "/=" | "%=" | "<<=" | ">>=" | "&=" | "^=" | "|=" => Some(Some(&op[..op.len() - 1])),
+                _ => None,
+            };
+            if let Some(compound) = compound {
+                let name = name.clone();
+                self.pos += 2;
+                let value = self.assignment()?;
+                let compound = compound.map(binary_op);
+                return Ok(Expr::Assign(name, compound, Box::new(value)));
+            }
+        }
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> Result<Expr, ArithError> {
+        let cond = self.binary(0)?;
+        if self.peek_op() != Some("?") {
+            return Ok(cond);
+        }
+        self.pos += 1;
+        let then = self.comma()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        Ok(Expr::Cond(Box::new(cond), Box::new(then), Box::new(otherwise)))
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, ArithError> {
+        if level == BINARY_LEVELS.len() {
+            return self.power();
+        }
+        let mut expr = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| BINARY_LEVELS[level].contains(op)) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn power(&mut self) -> Result<Expr, ArithError> {
+        let base = self.unary()?;
+        if self.peek_op() == Some("**") {
+            self.pos += 1;
+            let exponent = self.power()?;
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ArithError> {
+        match self.peek_op() {
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                match self.peek().cloned() {
+                    Some(Tok::Name(name)) => {
+                        self.pos += 1;
+                        let delta = if op == "++" { 1 } else { -1 };
+                        Ok(Expr::Step { name, delta, prefix: true })
+                    }
+                    _ => Err(ArithError::NotAVariable),
+                }
+            }
+            Some(op @ ("+" | "-" | "!" | "~")) => {
+                self.pos += 1;
+                let operand = self.unary()?;
+                Ok(Expr::Unary(op, Box::new(operand)))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expr, ArithError> {
+        let expr = self.primary()?;
+        if let (Expr::Var(name), Some(op @ ("++" | "--"))) = (&expr, self.peek_op()) {
+            self.pos += 1;
+            let delta = if op == "++" { 1 } else { -1 };
+            return Ok(Expr::Step { name: name.clone(), delta, prefix: false });
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ArithError> {
+        match self.peek().cloned() {
+            Some(Tok::Num(text)) => {
+                self.pos += 1;
+                Ok(Expr::Num(parse_number(&text)?))
+            }
+            Some(Tok::Name(name)) => {
+                self.pos += 1;
+                if !is_name(&name) {
+                    return Err(ArithError::Syntax(name));
+                }
+                Ok(Expr::Var(name))
+            }
+            Some(Tok::Op("(")) => {
+                self.pos += 1;
+                let expr = self.comma()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => Err(ArithError::OperandExpected(self.error_token())),
+        }
+    }
+}
+
+/// Maps the operator part of a compound assignment back to its static name.
+fn binary_op(op: &str) -> &'static str {
+    BINARY_LEVELS.iter().flat_map(|level| level.iter()).find(|o| **o == op).copied().unwrap()
+}
+
+struct Evaluator<'a> {
+    vars: &'a mut Variables,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn var(&mut self, name: &str) -> Result<i64, ArithError> {
+        let value = self.vars.get(name).unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(n) = value.parse::<i64>() {
+            return Ok(n);
+        }
+        // a variable may itself hold an expression
+        if self.depth >= MAX_DEPTH {
+            return Err(ArithError::TooDeep);
+        }
+        let expr = parse(value)?;
+        self.depth += 1;
+        let result = self.eval(&expr);
+        self.depth -= 1;
+        result
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<i64, ArithError> {
+        match expr {
+            Expr::Num(n) => Ok(*n),
+            Expr::Var(name) => self.var(name),
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                match *op {
+                    "+" => Ok(value),
+                    "-" => value.checked_neg().ok_or(ArithError::Overflow),
+                    "!" => Ok((value == 0) as i64),
+                    "~" => Ok(!value),
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Binary("&&", lhs, rhs) => Ok((self.eval(lhs)? != 0 && self.eval(rhs)? != 0) as i64),
+            Expr::Binary("||", lhs, rhs) => Ok((self.eval(lhs)? != 0 || self.eval(rhs)? != 0) as i64),
+            Expr::Binary(",", lhs, rhs) => {
+                self.eval(lhs)?;
+                self.eval(rhs)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(lhs)?;
+                let rhs = self.eval(rhs)?;
+                apply(op, lhs, rhs)
+            }
+            Expr::Assign(name, op, value) => {
+                let mut value = self.eval(value)?;
+                if let Some(op) = op {
+                    value = apply(op, self.var(name)?, value)?;
+                }
+                self.vars.set(name, value.to_string());
+                Ok(value)
+            }
+            Expr::Step { name, delta, prefix } => {
+                let old = self.var(name)?;
+                let new = old.checked_add(*delta).ok_or(ArithError::Overflow)?;
+                self.vars.set(name, new.to_string());
+                Ok(if *prefix { new } else { old })
+            }
+            Expr::Cond(cond, then, otherwise) => {
+                if self.eval(cond)? != 0 {
+                    self.eval(then)
+                } else {
+                    self.eval(otherwise)
+                }
+            }
+        }
+    }
+}
+
+fn apply(op: &str, lhs: i64, rhs: i64) -> Result<i64, ArithError> {
+    let checked = |value: Option<i64>| value.ok_or(ArithError::Overflow);
+    match op {
+        "+" => checked(lhs.checked_add(rhs)),
+        "-" => checked(lhs.checked_sub(rhs)),
+        "*" => checked(lhs.checked_mul(rhs)),
+        "/" | "%" if rhs == 0 => Err(ArithError::DivisionByZero),
+        "/" => checked(lhs.checked_div(rhs)),
+        "%" => checked(lhs.checked_rem(rhs)),
+        "**" => {
+            if rhs < 0 {
+                return Err(ArithError::NegativeExponent);
+            }
+            let exponent = u32::try_from(rhs).map_err(|_| ArithError::Overflow)?;
+            checked(lhs.checked_pow(exponent))
+        }
+        "<<" => checked(u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shl(rhs))),
+        ">>" => checked(u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shr(rhs))),
+        "<" => Ok((lhs < rhs) as i64),
+        "<=" => Ok((lhs <= rhs) as i64),
+        ">" => Ok((lhs > rhs) as i64),
+        ">=" => Ok((lhs >= rhs) as i64),
+        "==" => Ok((lhs == rhs) as i64),
+        "!=" => Ok((lhs != rhs) as i64),
+        "&" => Ok(lhs & rhs),
+        "^" => Ok(lhs ^ rhs),
+        "|" => Ok(lhs | rhs),
+        _ => unreachable!("unknown operator {}", op),
+    }
+}
+
+fn parse(input: &str) -> Result<Expr, ArithError> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        return Ok(Expr::Num(0));
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.comma()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(ArithError::Syntax(parser.error_token()));
+    }
+    Ok(expr)
+}
+
+/// Evaluates an integer expression, reading and assigning shell variables.
+pub fn evaluate(input: &str, vars: &mut Variables) -> Result<i64, ArithError> {
+    let expr = parse(input)?;
+    Evaluator { vars, depth: 0 }.eval(&expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str) -> Result<i64, ArithError> {
+        evaluate(input, &mut Variables::default())
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("-2 ** 2"), Ok(4));
+        assert_eq!(eval("1 + 2 < 4 && 3 | 4 == 7"), Ok(1));
+        assert_eq!(eval("1 << 2 + 1"), Ok(8));
+        assert_eq!(eval("!0 + ~0"), Ok(0));
+        assert_eq!(eval("7 / 2 + 7 % 2"), Ok(4));
+    }
+
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("(2 ** 3) ** 2"), Ok(64));
+    }
+
+    #[test]
+    fn conditional_and_comma() {
+        assert_eq!(eval("1 ? 2 : 3"), Ok(2));
+        assert_eq!(eval("0 ? 2 : 0 ? 3 : 4"), Ok(4));
+        assert_eq!(eval("1, 2, 3"), Ok(3));
+        let mut vars = Variables::default();
+        assert_eq!(evaluate("a = 1, b = a + 1, a + b", &mut vars), Ok(3));
+        // only the chosen branch is evaluated
+        assert_eq!(evaluate("1 ? c = 5 : (d = 6)", &mut vars), Ok(5));
+        assert_eq!(vars.get("d"), None);
+    }
+
+    #[test]
+    fn assignments_and_increments() {
+        let mut vars = Variables::default();
+        vars.set("i", "5".to_string());
+        assert_eq!(evaluate("i += 2", &mut vars), Ok(7));
+        assert_eq!(evaluate("i *= 3", &mut vars), Ok(21));
+        assert_eq!(evaluate("i++", &mut vars), Ok(21));
+        assert_eq!(vars.get("i").as_deref(), Some("22"));
+        assert_eq!(evaluate("++i", &mut vars), Ok(23));
+        assert_eq!(evaluate("i--", &mut vars), Ok(23));
+        assert_eq!(evaluate("--i", &mut vars), Ok(21));
+        assert_eq!(evaluate("unset_var + 1", &mut vars), Ok(1));
+        assert_eq!(eval("++1"), Err(ArithError::NotAVariable));
+    }
+
+    #[test]
+    fn number_bases() {
+        assert_eq!(eval("0x1F"), Ok(31));
+        assert_eq!(eval("010"), Ok(8));
+        assert_eq!(eval("2#101"), Ok(5));
+        assert_eq!(eval("16#ff"), Ok(255));
+        assert_eq!(eval("36#z"), Ok(35));
+        assert_eq!(eval("08"), Err(ArithError::InvalidNumber));
+        assert_eq!(eval("2#102"), Err(ArithError::InvalidNumber));
+        assert_eq!(ArithError::InvalidNumber.to_string(), "invalid number");
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("1 / 0"), Err(ArithError::DivisionByZero));
+        assert_eq!(eval("1 % 0"), Err(ArithError::DivisionByZero));
+        let mut vars = Variables::default();
+        assert_eq!(evaluate("x = 4, x /= 0", &mut vars), Err(ArithError::DivisionByZero));
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(eval("2 ** 62"), Ok(1 << 62));
+        assert_eq!(eval("2 ** 63"), Err(ArithError::Overflow));
+        assert_eq!(eval("-9223372036854775807 - 1"), Ok(i64::MIN));
+        assert_eq!(eval("(-9223372036854775807 - 1) / -1"), Err(ArithError::Overflow));
+        assert_eq!(eval("9223372036854775807 + 1"), Err(ArithError::Overflow));
+    }
+
+    #[test]
+    fn negative_exponent() {
+        assert_eq!(eval("2 ** -1"), Err(ArithError::NegativeExponent));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(eval(""), Ok(0));
+        assert!(matches!(eval("1 +"), Err(ArithError::OperandExpected(_))));
+        assert!(matches!(eval("(1"), Err(ArithError::Syntax(_))));
+        assert!(matches!(eval("1 2"), Err(ArithError::Syntax(_))));
+    }
+}
@@ -0,0 +1,129 @@
+use std::rc::Rc;
+
+/// A word as it appeared in the input, quotes and escapes included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word(pub String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectKind {
+    /// `>` and `>|`
+    Write,
+    /// `>>`
+    Append,
+    /// `<`
+    Read,
+    /// `<>`
+    ReadWrite,
+    /// `<&` and `>&`: the target is a descriptor to copy, or `-` to close.
+    /// `>&word` with a non-numeric word acts like `&>word`.
+    DupRead,
+    DupWrite,
+    /// `&>`: stdout and stderr to the same file.
+    WriteAll,
+    /// `&>>`
+    AppendAll,
+    /// `<<` and `<<-`; the target holds the body. `expand` is false when
+    /// the delimiter was quoted.
+    HereDoc { expand: bool },
+    /// `<<<`
+    HereString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub fd: i32,
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+/// `name=value` in front of a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    /// `(( expr ))`
+    Arith(Word),
+    /// A compound command and the redirects that follow it.
+    Compound(CompoundCommand, Vec<Redirect>),
+    /// `name() compound-command`
+    FunctionDef { name: String, body: Rc<Command> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompoundCommand {
+    /// `( list )`, run in a child process.
+    Subshell(List),
+    /// `{ list; }`, run in the current shell.
+    Group(List),
+    /// `if cond; then body; elif cond; then body; else body; fi`. Each
+    /// branch pairs a condition with its body.
+    If { branches: Vec<(List, List)>, else_body: Option<List> },
+    /// `while cond; do body; done`, or `until` when `until` is set, which
+    /// runs the body while the condition fails.
+    While { condition: List, body: List, until: bool },
+    /// `for name in words; do body; done`. Without `in`, `words` is `None`
+    /// and the loop runs over the positional parameters.
+    For { name: String, words: Option<Vec<Word>>, body: List },
+    /// `for (( init; condition; step )); do body; done`. An empty condition
+    /// is always true.
+    ArithFor { init: Word, condition: Word, step: Word, body: List },
+    /// `case word in pattern) body;; ... esac`
+    Case { word: Word, items: Vec<CaseItem> },
+}
+
+/// One `pat1|pat2) body` arm of a `case` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+    pub terminator: CaseTerminator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseTerminator {
+    /// `;;`: stop after this arm.
+    Break,
+    /// `;&`: also run the next arm's body without testing it.
+    FallThrough,
+    /// `;;&`: go on testing the following patterns.
+    Continue,
+}
+
+/// Commands joined by `|`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AndOrOp {
+    /// `&&`: run the next pipeline only if the previous one succeeded.
+    And,
+    /// `||`: run the next pipeline only if the previous one failed.
+    Or,
+}
+
+/// Pipelines joined by `&&` and `||`, evaluated left to right.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(AndOrOp, Pipeline)>,
+}
+
+/// And-or lists separated by `;` or newlines, run one after the other.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
@@ -0,0 +1,222 @@
+use crate::lexer;
+
+/// Expands `{a,b}` alternatives and `{x..y[..step]}` sequences in the raw
+/// text of a word. Quoted braces, `${...}` and `$(...)` are left alone;
+/// `comments` says whether `#` starts a comment inside the latter. A word
+/// with nothing to expand comes back unchanged.
+pub fn expand(word: &str, comments: bool) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '\'' | '"' | '`' => i = skip_quoted(&chars, i),
+            '$' if matches!(chars.get(i + 1), Some('{') | Some('(')) => i = skip_group(&chars, i + 1, comments),
+            '{' => {
+                if let Some((end, items)) = alternatives(&chars, i, comments) {
+                    let prefix: String = chars[..i].iter().collect();
+                    let suffix: String = chars[end + 1..].iter().collect();
+                    return items
+                        .iter()
+                        .flat_map(|item| expand(&format!("{}{}{}", prefix, item, suffix), comments))
+                        .collect();
+                }
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    vec![word.to_string()]
+}
+
+/// Returns the index just past the quoted string starting at `start`.
+fn skip_quoted(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() && chars[i] != quote {
+        if chars[i] == '\\' && quote != '\'' {
+            i += 1;
+        }
+        i += 1;
+    }
+    i + 1
+}
+
+/// Returns the index just past the bracketed group opening at `start`.
+fn skip_group(chars: &[char], start: usize, comments: bool) -> usize {
+    if chars[start] == '(' && chars.get(start + 1) != Some(&'(') {
+        return lexer::command_substitution_end(chars, start + 1, comments).unwrap_or(chars.len());
+    }
+    let (open, close) = if chars[start] == '{' { ('{', '}') } else { ('(', ')') };
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' | '"' | '`' => {
+                i = skip_quoted(chars, i);
+                continue;
+            }
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    i
+}
+
+/// Reads the brace expression opening at `start`. Returns the index of its
+/// closing brace and the items it expands to, or `None` if it is not a
+/// valid brace expression.
+fn alternatives(chars: &[char], start: usize, comments: bool) -> Option<(usize, Vec<String>)> {
+    let mut depth = 0;
+    let mut commas = vec![];
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' | '"' | '`' => {
+                i = skip_quoted(chars, i);
+                continue;
+            }
+            '$' if matches!(chars.get(i + 1), Some('{') | Some('(')) => {
+                i = skip_group(chars, i + 1, comments);
+                continue;
+            }
+            '{' => depth += 1,
+            ',' if depth == 1 => commas.push(i),
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if i >= chars.len() {
+        return None;
+    }
+    let end = i;
+    if commas.is_empty() {
+        let body: String = chars[start + 1..end].iter().collect();
+        return sequence(&body).map(|items| (end, items));
+    }
+    let mut items = vec![];
+    let mut from = start + 1;
+    for comma in commas.into_iter().chain(std::iter::once(end)) {
+        items.push(chars[from..comma].iter().collect());
+        from = comma + 1;
+    }
+    Some((end, items))
+}
+
+/// Expands the body of a `{x..y[..step]}` sequence of integers or letters.
+fn sequence(body: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = body.split("..").collect();
+    let (first, last, step) = match parts.as_slice() {
+        [first, last] => (*first, *last, 1),
+        [first, last, step] => (*first, *last, step.parse::<i64>().ok()?),
+        _ => return None,
+    };
+    let step = match step.unsigned_abs() {
+        0 => 1,
+        step => step,
+    };
+    if let (Ok(start), Ok(end)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        let padded = |s: &str| s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0');
+        let width = if padded(first) || padded(last) { first.len().max(last.len()) } else { 0 };
+        return Some(range(start, end, step)?.map(|n| format!("{:0width$}", n, width = width)).collect());
+    }
+    let mut first_chars = first.chars();
+    let mut last_chars = last.chars();
+    match (first_chars.next(), first_chars.next(), last_chars.next(), last_chars.next()) {
+        (Some(a), None, Some(b), None) if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => Some(
+            range(a as i64, b as i64, step)?
+                .map(|c| char::from(c as u8).to_string())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// The values from `start` to `end` inclusive, counting up or down by `step`,
+/// or `None` if there are too many to count.
+fn range(start: i64, end: i64, step: u64) -> Option<impl Iterator<Item = i64>> {
+    let up = start <= end;
+    let count = (start.abs_diff(end) / step).checked_add(1)?;
+    // every value lies between `start` and `end`, so wrapping never happens
+    Some((0..count).map(move |i| {
+        let offset = i * step;
+        if up { start.wrapping_add_unsigned(offset) } else { start.wrapping_sub_unsigned(offset) }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expands_to(word: &str, expected: &[&str]) {
+        assert_eq!(expand(word, true), expected, "{}", word);
+    }
+
+    #[test]
+    fn alternatives() {
+        expands_to("a{b,c}d", &["abd", "acd"]);
+        expands_to("a{,b}", &["a", "ab"]);
+        expands_to("{a,{b,c}}", &["a", "b", "c"]);
+        expands_to("{a,b}{1,2}", &["a1", "a2", "b1", "b2"]);
+        expands_to("x{a,b{1,2}}y", &["xay", "xb1y", "xb2y"]);
+    }
+
+    #[test]
+    fn numeric_sequences() {
+        expands_to("{1..3}", &["1", "2", "3"]);
+        expands_to("{5..1}", &["5", "4", "3", "2", "1"]);
+        expands_to("{01..05}", &["01", "02", "03", "04", "05"]);
+        expands_to("{8..010}", &["008", "009", "010"]);
+        expands_to("{1..10..3}", &["1", "4", "7", "10"]);
+        expands_to("{10..1..-4}", &["10", "6", "2"]);
+        expands_to("{-1..1}", &["-1", "0", "1"]);
+        expands_to(
+            "{-9223372036854775808..9223372036854775807..9223372036854775807}",
+            &["-9223372036854775808", "-1", "9223372036854775806"],
+        );
+    }
+
+    #[test]
+    fn character_sequences() {
+        expands_to("{a..e..2}", &["a", "c", "e"]);
+        expands_to("{c..a}", &["c", "b", "a"]);
+        expands_to("{a..c}{1..2}", &["a1", "a2", "b1", "b2", "c1", "c2"]);
+    }
+
+    #[test]
+    fn left_alone() {
+        expands_to("${x}", &["${x}"]);
+        expands_to("${x}{a,b}", &["${x}a", "${x}b"]);
+        expands_to("\"{x,y}\"", &["\"{x,y}\""]);
+        expands_to("'{x,y}'", &["'{x,y}'"]);
+        expands_to("\\{p,q\\}", &["\\{p,q\\}"]);
+        expands_to("$(echo {a,b})", &["$(echo {a,b})"]);
+        expands_to("{x}", &["{x}"]);
+        expands_to("{}", &["{}"]);
+        expands_to("{a..}", &["{a..}"]);
+        expands_to("{1..a}", &["{1..a}"]);
+        expands_to("{a,b", &["{a,b"]);
+        expands_to("{-9223372036854775808..9223372036854775807}", &["{-9223372036854775808..9223372036854775807}"]);
+    }
+
+    #[test]
+    fn comments_in_command_substitution() {
+        assert_eq!(expand("{a,b}$(x # )\n{c,d})", true), ["a$(x # )\n{c,d})", "b$(x # )\n{c,d})"]);
+        assert_eq!(expand("$(x # ){c,d}", false), ["$(x # )c", "$(x # )d"]);
+    }
+}
@@ -0,0 +1,612 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use crate::arith;
+use crate::expand::DEFAULT_IFS;
+use crate::input::Input;
+use crate::line_buffer::{history_file_entries, history_file_entry};
+use crate::print;
+use crate::shell::{find_executable, io_error_message, Control, Shell};
+use crate::sys;
+use crate::variables::is_name;
+
+pub const BUILTINS: &[&str] = &[
+    "echo", "exit", "type", "pwd", "cd", "history", "export", "unset", "env", "let", "read", "break", "continue",
+    "return", "local", "shift", "set", "source", ".", "alias", "unalias", "shopt",
+];
+
+/// The options `shopt` knows about.
+const SHELL_OPTIONS: &[&str] = &["interactive_comments"];
+
+impl Shell {
+    /// Runs the builtin named by `args[0]` and returns its exit status.
+    pub fn run_builtin(&mut self, args: &[String]) -> i32 {
+        match args[0].as_str() {
+            "echo" => self.builtin_echo(args),
+            "exit" => self.builtin_exit(args),
+            "type" => self.builtin_type(args),
+            "pwd" => self.builtin_pwd(args),
+            "cd" => self.builtin_cd(args),
+            "history" => self.builtin_history(args),
+            "export" => self.builtin_export(args),
+            "unset" => self.builtin_unset(args),
+            "env" => self.builtin_env(args),
+            "let" => self.builtin_let(args),
+            "read" => self.builtin_read(args),
+            "break" => self.builtin_loop_control(args, Control::Break),
+            "continue" => self.builtin_loop_control(args, Control::Continue),
+            "return" => self.builtin_return(args),
+            "local" => self.builtin_local(args),
+            "shift" => self.builtin_shift(args),
+            "set" => self.builtin_set(args),
+            "source" | "." => self.builtin_source(args),
+            "alias" => self.builtin_alias(args),
+            "unalias" => self.builtin_unalias(args),
+            "shopt" => self.builtin_shopt(args),
+            _ => unreachable!("{} is not a builtin", args[0]),
+        }
+    }
+
+    fn builtin_echo(&mut self, args: &[String]) -> i32 {
+        println!("{}", args[1..].join(" "));
+        0
+    }
+
+    fn builtin_exit(&mut self, args: &[String]) -> i32 {
+        let code = match args.get(1) {
+            Some(arg) => match arg.parse::<i64>() {
+                Ok(code) => code as i32 & 0xff,
+                Err(_) => {
+                    eprintln!("exit: {}: numeric argument required", arg);
+                    2
+                }
+            },
+            None => self.last_status,
+        };
+        self.exit_code = Some(code);
+        code
+    }
+
+    fn builtin_type(&mut self, args: &[String]) -> i32 {
+        let mut status = 0;
+        for name in &args[1..] {
+            if let Some(value) = self.aliases.get(name) {
+                println!("{} is aliased to `{}'", name, value);
+            } else if let Some(body) = self.functions.get(name) {
+                println!("{} is a function", name);
+                println!("{}", print::function_definition(name, body));
+            } else if BUILTINS.contains(&name.as_str()) {
+                println!("{} is a shell builtin", name);
+            } else if let Some(executable_path) = find_executable(name, &self.path()) {
+                println!("{} is {}", name, executable_path);
+            } else {
+                eprintln!("{}: not found", name);
+                status = 1;
+            }
+        }
+        status
+    }
+
+    fn builtin_pwd(&mut self, _args: &[String]) -> i32 {
+        println!("{}", self.current_dir.to_str().unwrap());
+        0
+    }
+
+    fn builtin_cd(&mut self, args: &[String]) -> i32 {
+        if args.len() == 1 {
+            return 0;
+        }
+        let mut path = PathBuf::from(&args[1]);
+        if path.is_relative() {
+            let mut path_built: PathBuf = self.current_dir.clone();
+            for part in path.iter() {
+                if part == "." {
+                    continue;
+                } else if part == ".." {
+                    path_built.pop();
+                } else {
+                    path_built = path_built.join(part);
+                }
+            }
+            path = path_built;
+        }
+        if path.is_dir() {
+            let old_dir = std::mem::replace(&mut self.current_dir, path);
+            self.vars.set("OLDPWD", old_dir.to_string_lossy().into_owned());
+            self.vars.set("PWD", self.current_dir.to_string_lossy().into_owned());
+            0
+        } else {
+            eprintln!("cd: {}: No such file or directory", args[1]);
+            1
+        }
+    }
+
+    fn builtin_history(&mut self, args: &[String]) -> i32 {
+        let interactive = self.interactive;
+        let history = self.line_reader.get_history();
+        let mut start = 0;
+        if args.len() > 1 {
+            if let Ok(count) = args[1].parse::<usize>() {
+                start = history.len().saturating_sub(count);
+            } else {
+                let args = &args[1..];
+                if args.len() != 2 {
+                    eprintln!("history: usage: history [n] | history -r|-w|-a file");
+                    return 2;
+                }
+                let file_path = self.resolve_path(&args[1]);
+                let result = match args[0].as_str() {
+                    // read
+                    "-r" => fs::read_to_string(file_path).map(|file_contents| {
+                        for entry in history_file_entries(&file_contents) {
+                            self.line_reader.insert_history_entry(&entry, interactive);
+                        }
+                    }),
+                    // write
+                    "-w" => OpenOptions::new().create(true).write(true).truncate(true).open(file_path).and_then(|mut file| {
+                        for entry in &history {
+                            file.write_all(history_file_entry(entry).as_bytes())?;
+                        }
+                        Ok(())
+                    }),
+                    // append
+                    "-a" => OpenOptions::new().create(false).append(true).open(file_path).and_then(|mut file| {
+                        for entry in &history[self.history_appended..] {
+                            file.write_all(history_file_entry(entry).as_bytes())?;
+                            self.history_appended += 1;
+                        }
+                        Ok(())
+                    }),
+                    option => {
+                        eprintln!("history: {}: invalid option", option);
+                        return 2;
+                    }
+                };
+                if let Err(err) = result {
+                    eprintln!("history: {}: {}", args[1], io_error_message(&err));
+                    return 1;
+                }
+                return 0;
+            }
+        }
+        for (command_num, entry) in history.iter().enumerate().skip(start) {
+            println!("    {}  {}", command_num + 1, entry);
+        }
+        0
+    }
+
+    fn builtin_export(&mut self, args: &[String]) -> i32 {
+        let names: Vec<&String> = args[1..].iter().filter(|arg| *arg != "-p").collect();
+        if names.is_empty() {
+            for (name, value) in self.vars.exported() {
+                println!("export {}={}", name, shell_quote(&value));
+            }
+            return 0;
+        }
+        let mut status = 0;
+        for arg in names {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !is_name(name) {
+                eprintln!("export: `{}': not a valid identifier", arg);
+                status = 1;
+                continue;
+            }
+            if let Some(value) = value {
+                self.vars.set(name, value.to_string());
+            }
+            self.vars.export(name);
+        }
+        status
+    }
+
+    /// `local [-x] name[=value]...`: declares variables in the current
+    /// function's scope; `-x` also exports them until it returns.
+    fn builtin_local(&mut self, args: &[String]) -> i32 {
+        if self.function_depth == 0 {
+            eprintln!("local: can only be used in a function");
+            return 1;
+        }
+        let mut exported = false;
+        let mut status = 0;
+        for arg in &args[1..] {
+            if arg == "-x" {
+                exported = true;
+                continue;
+            }
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            if !is_name(name) {
+                eprintln!("local: `{}': not a valid identifier", arg);
+                status = 1;
+                continue;
+            }
+            self.vars.set_local(name, value, exported);
+        }
+        status
+    }
+
+    /// `source file [arg...]`, also spelled `.`: runs the commands in `file`
+    /// in the current shell, with the arguments as positional parameters
+    /// while it runs.
+    fn builtin_source(&mut self, args: &[String]) -> i32 {
+        let Some(name) = args.get(1) else {
+            eprintln!("{}: filename argument required", args[0]);
+            return 2;
+        };
+        let path = if name.contains('/') {
+            self.resolve_path(name)
+        } else {
+            // like bash, fall back to the current directory if PATH has no match
+            find_in_path(name, &self.path()).unwrap_or_else(|| self.resolve_path(name))
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!("{}: {}: {}", args[0], name, io_error_message(&err));
+                return 1;
+            }
+        };
+        let saved_positional = (args.len() > 2).then(|| std::mem::replace(&mut self.positional, args[2..].to_vec()));
+        self.source_depth += 1;
+        let status = self.run_input(&mut Input::from_text(&contents));
+        self.source_depth -= 1;
+        if let Some(positional) = saved_positional {
+            self.positional = positional;
+        }
+        if self.control == Some(Control::Return) {
+            self.control = None;
+        }
+        status
+    }
+
+    /// `alias [-p] [name[=value]...]`: defines aliases, or prints them in a
+    /// form that can be read back in.
+    fn builtin_alias(&mut self, args: &[String]) -> i32 {
+        let names: Vec<&String> = args[1..].iter().filter(|arg| *arg != "-p").collect();
+        if names.is_empty() {
+            for (name, value) in &self.aliases {
+                println!("alias {}={}", name, shell_quote(value));
+            }
+            return 0;
+        }
+        let mut status = 0;
+        for arg in names {
+            match arg.split_once('=') {
+                Some((name, _)) if !is_alias_name(name) => {
+                    eprintln!("alias: `{}': invalid alias name", name);
+                    status = 1;
+                }
+                Some((name, value)) => {
+                    self.aliases.insert(name.to_string(), value.to_string());
+                }
+                None => match self.aliases.get(arg) {
+                    Some(value) => println!("alias {}={}", arg, shell_quote(value)),
+                    None => {
+                        eprintln!("alias: {}: not found", arg);
+                        status = 1;
+                    }
+                },
+            }
+        }
+        self.line_reader.set_aliases(self.aliases.keys().cloned().collect());
+        status
+    }
+
+    /// `unalias -a | name...`
+    fn builtin_unalias(&mut self, args: &[String]) -> i32 {
+        if args.len() == 1 {
+            eprintln!("unalias: usage: unalias [-a] name [name ...]");
+            return 2;
+        }
+        let mut status = 0;
+        for name in &args[1..] {
+            if name == "-a" {
+                self.aliases.clear();
+            } else if self.aliases.remove(name).is_none() {
+                eprintln!("unalias: {}: not found", name);
+                status = 1;
+            }
+        }
+        self.line_reader.set_aliases(self.aliases.keys().cloned().collect());
+        status
+    }
+
+    /// `shopt [-s | -u] [-q] [name...]`: turns shell options on or off, or
+    /// reports whether they are on.
+    fn builtin_shopt(&mut self, args: &[String]) -> i32 {
+        let mut set = None;
+        let mut quiet = false;
+        let mut names = vec![];
+        for arg in &args[1..] {
+            match arg.as_str() {
+                "-s" => set = Some(true),
+                "-u" => set = Some(false),
+                "-q" => quiet = true,
+                option if option.starts_with('-') => {
+                    eprintln!("shopt: {}: invalid option", option);
+                    return 2;
+                }
+                name => names.push(name),
+            }
+        }
+        if names.is_empty() {
+            for name in SHELL_OPTIONS {
+                let on = *self.shell_option(name).unwrap();
+                if !quiet && set.map_or(true, |set| set == on) {
+                    print_shell_option(name, on);
+                }
+            }
+            return 0;
+        }
+        let mut status = 0;
+        for name in names {
+            let Some(option) = self.shell_option(name) else {
+                eprintln!("shopt: {}: invalid shell option name", name);
+                status = 1;
+                continue;
+            };
+            match set {
+                Some(on) => *option = on,
+                None => {
+                    let on = *option;
+                    if !on {
+                        status = 1;
+                    }
+                    if !quiet {
+                        print_shell_option(name, on);
+                    }
+                }
+            }
+        }
+        status
+    }
+
+    /// The flag behind the `shopt` option `name`.
+    fn shell_option(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "interactive_comments" => Some(&mut self.interactive_comments),
+            _ => None,
+        }
+    }
+
+    /// `shift [n]`: drops the first `n` positional parameters.
+    fn builtin_shift(&mut self, args: &[String]) -> i32 {
+        let count = match args.get(1).map(|arg| arg.parse::<usize>()) {
+            None => 1,
+            Some(Ok(count)) => count,
+            Some(Err(_)) => {
+                eprintln!("shift: {}: numeric argument required", args[1]);
+                return 1;
+            }
+        };
+        if count > self.positional.len() {
+            return 1;
+        }
+        self.positional.drain(..count);
+        0
+    }
+
+    /// `set [--] [arg...]`: replaces the positional parameters. With no
+    /// arguments, lists the shell's variables.
+    fn builtin_set(&mut self, args: &[String]) -> i32 {
+        match args.get(1).map(String::as_str) {
+            None => {
+                for (name, value) in self.vars.all() {
+                    println!("{}={}", name, shell_quote(&value));
+                }
+            }
+            Some("--") => self.positional = args[2..].to_vec(),
+            Some(option) if option.starts_with(['-', '+']) => {
+                eprintln!("set: {}: invalid option", option);
+                return 2;
+            }
+            Some(_) => self.positional = args[1..].to_vec(),
+        }
+        0
+    }
+
+    fn builtin_unset(&mut self, args: &[String]) -> i32 {
+        let mut status = 0;
+        for name in args[1..].iter().filter(|arg| *arg != "-v") {
+            if !is_name(name) {
+                eprintln!("unset: `{}': not a valid identifier", name);
+                status = 1;
+                continue;
+            }
+            self.vars.unset(name);
+        }
+        status
+    }
+
+    /// `env [-i] [-u name] [name=value]... [command [args]...]`
+    fn builtin_env(&mut self, args: &[String]) -> i32 {
+        let mut env = self.vars.exported();
+        let mut i = 1;
+        while let Some(arg) = args.get(i) {
+            if arg == "-i" || arg == "-" {
+                env.clear();
+            } else if arg == "-u" {
+                i += 1;
+                let Some(name) = args.get(i) else {
+                    eprintln!("env: option requires an argument -- 'u'");
+                    return 125;
+                };
+                env.retain(|(n, _)| n != name);
+            } else if let Some((name, value)) = arg.split_once('=') {
+                env.retain(|(n, _)| n != name);
+                env.push((name.to_string(), value.to_string()));
+            } else {
+                break;
+            }
+            i += 1;
+        }
+        if i < args.len() {
+            return self.run_external(&args[i..], &env);
+        }
+        for (name, value) in env {
+            println!("{}={}", name, value);
+        }
+        0
+    }
+
+    /// `let expr...`: evaluates each expression; succeeds if the last one is
+    /// non-zero.
+    fn builtin_let(&mut self, args: &[String]) -> i32 {
+        if args.len() == 1 {
+            eprintln!("let: expression expected");
+            return 1;
+        }
+        let mut value = 0;
+        for expr in &args[1..] {
+            match arith::evaluate(expr, &mut self.vars) {
+                Ok(result) => value = result,
+                Err(err) => {
+                    eprintln!("let: {}: {}", expr, err);
+                    return 1;
+                }
+            }
+        }
+        (value == 0) as i32
+    }
+
+    /// `read [-r] [name...]`: reads a line from stdin and assigns its fields
+    /// to the names, the last name taking the rest of the line. Without `-r`
+    /// a backslash escapes the next character and joins continued lines.
+    fn builtin_read(&mut self, args: &[String]) -> i32 {
+        let raw = args.get(1).is_some_and(|arg| arg == "-r");
+        let mut names: Vec<&str> = args[1 + raw as usize..].iter().map(String::as_str).collect();
+        if let Some(name) = names.iter().find(|name| !is_name(name)) {
+            eprintln!("read: `{}': not a valid identifier", name);
+            return 1;
+        }
+        if names.is_empty() {
+            names.push("REPLY");
+        }
+        // each byte of the line and whether it was escaped
+        let mut line: Vec<(u8, bool)> = vec![];
+        let mut at_eof = false;
+        loop {
+            let byte = match sys::read_byte(0) {
+                Ok(Some(byte)) => byte,
+                Ok(None) => {
+                    at_eof = true;
+                    break;
+                }
+                Err(err) => {
+                    eprintln!("read: read error: {}", io_error_message(&err));
+                    return 1;
+                }
+            };
+            match byte {
+                b'\n' => break,
+                b'\\' if !raw => match sys::read_byte(0) {
+                    Ok(Some(b'\n')) => {}
+                    Ok(Some(next)) => line.push((next, true)),
+                    _ => {
+                        at_eof = true;
+                        break;
+                    }
+                },
+                _ => line.push((byte, false)),
+            }
+        }
+        let ifs = self.vars.get("IFS").unwrap_or(DEFAULT_IFS.to_string());
+        let is_ifs = |&(byte, escaped): &(u8, bool)| !escaped && ifs.as_bytes().contains(&byte);
+        let is_ifs_space = |b: &(u8, bool)| is_ifs(b) && b.0.is_ascii_whitespace();
+        let text = |bytes: &[(u8, bool)]| String::from_utf8_lossy(&bytes.iter().map(|b| b.0).collect::<Vec<u8>>()).into_owned();
+        let mut rest = &line[..];
+        while rest.first().is_some_and(is_ifs_space) {
+            rest = &rest[1..];
+        }
+        let (last, leading) = names.split_last().unwrap();
+        for name in leading {
+            let end = rest.iter().position(is_ifs).unwrap_or(rest.len());
+            self.vars.set(name, text(&rest[..end]));
+            rest = &rest[end..];
+            while rest.first().is_some_and(is_ifs_space) {
+                rest = &rest[1..];
+            }
+            if rest.first().is_some_and(is_ifs) {
+                rest = &rest[1..];
+                while rest.first().is_some_and(is_ifs_space) {
+                    rest = &rest[1..];
+                }
+            }
+        }
+        while rest.last().is_some_and(is_ifs_space) {
+            rest = &rest[..rest.len() - 1];
+        }
+        self.vars.set(last, text(rest));
+        at_eof as i32
+    }
+
+    /// `break [n]` and `continue [n]`.
+    fn builtin_loop_control(&mut self, args: &[String], control: fn(usize) -> Control) -> i32 {
+        if self.loop_depth == 0 {
+            eprintln!("{}: only meaningful in a `for', `while', or `until' loop", args[0]);
+            return 0;
+        }
+        let count = match args.get(1).map(|arg| arg.parse::<i64>()) {
+            None => 1,
+            Some(Ok(count)) if count >= 1 => count as usize,
+            Some(Ok(_)) => {
+                eprintln!("{}: {}: loop count out of range", args[0], args[1]);
+                return 1;
+            }
+            Some(Err(_)) => {
+                eprintln!("{}: {}: numeric argument required", args[0], args[1]);
+                return 1;
+            }
+        };
+        self.control = Some(control(count.min(self.loop_depth)));
+        0
+    }
+
+    /// `return [n]`: leaves the current function with status `n`, or with the
+    /// status of the last command.
+    fn builtin_return(&mut self, args: &[String]) -> i32 {
+        if self.function_depth == 0 && self.source_depth == 0 {
+            eprintln!("return: can only `return' from a function or sourced script");
+            return 1;
+        }
+        let status = match args.get(1) {
+            Some(arg) => match arg.parse::<i64>() {
+                Ok(status) => status as i32 & 0xff,
+                Err(_) => {
+                    eprintln!("return: {}: numeric argument required", arg);
+                    2
+                }
+            },
+            None => self.last_status,
+        };
+        self.control = Some(Control::Return);
+        status
+    }
+}
+
+/// Whether `name` can be used as an alias: anything the lexer reads back as
+/// a single plain word.
+fn is_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(|c: char| c.is_whitespace() || "/$`'\"\\=|&;<>()".contains(c))
+}
+
+/// Finds a regular file called `name` in one of the directories of
+/// `path_var`, executable or not.
+fn find_in_path(name: &str, path_var: &str) -> Option<PathBuf> {
+    path_var.split(':').map(|dir| PathBuf::from(dir).join(name)).find(|path| path.is_file())
+}
+
+fn print_shell_option(name: &str, on: bool) {
+    println!("{:<15}\t{}", name, if on { "on" } else { "off" });
+}
+
+/// Quotes `s` so the shell reads it back as the same single word.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
@@ -0,0 +1,542 @@
+use crate::arith::{self, ArithError};
+use crate::ast::Word;
+use crate::brace;
+use crate::glob::{self, PatChar};
+use crate::lexer;
+use crate::parser::{self, ParseError};
+use crate::shell::{io_error_message, Shell};
+use crate::sys;
+use crate::variables::is_name;
+use thiserror::Error;
+
+pub const DEFAULT_IFS: &str = " \t\n";
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ExpandError {
+    #[error("{0}: bad substitution")]
+    BadSubstitution(String),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("command substitution: {0}")]
+    Substitution(String),
+    #[error("{0}: {1}")]
+    Arith(String, ArithError),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FieldChar {
+    c: char,
+    /// Quoted characters are never split or treated as pattern characters.
+    quoted: bool,
+    /// Set on characters produced by an unquoted expansion; only these are
+    /// subject to field splitting.
+    split: bool,
+}
+
+/// One field of an expanded word.
+#[derive(Debug, Clone, Default)]
+struct Field {
+    chars: Vec<FieldChar>,
+    /// Whether the word contained quotes, so that `""` still yields an
+    /// (empty) argument.
+    quoted: bool,
+}
+
+impl Field {
+    fn push(&mut self, c: char, quoted: bool) {
+        self.chars.push(FieldChar { c, quoted, split: false });
+    }
+
+    fn push_expansion(&mut self, value: &str, quoted: bool) {
+        for c in value.chars() {
+            self.chars.push(FieldChar { c, quoted, split: !quoted });
+        }
+    }
+
+    fn into_string(self) -> String {
+        self.chars.into_iter().map(|c| c.c).collect()
+    }
+
+    fn pattern(&self) -> Vec<PatChar> {
+        self.chars.iter().map(|c| (c.c, c.quoted)).collect()
+    }
+
+    /// Splits the field on IFS characters that came from unquoted expansions.
+    fn split(self, ifs: &str) -> Vec<Field> {
+        let is_ifs_space = |c: char| c.is_whitespace() && ifs.contains(c);
+        let mut fields = vec![];
+        let mut current = Field::default();
+        let mut has_content = false;
+        let mut chars = self.chars.into_iter().peekable();
+        while let Some(fc) = chars.next() {
+            if !(fc.split && ifs.contains(fc.c)) {
+                current.chars.push(fc);
+                has_content = true;
+                continue;
+            }
+            // a run of IFS whitespace, optionally around one other IFS character,
+            // makes up a single delimiter
+            let mut hard = !is_ifs_space(fc.c);
+            while let Some(next) = chars.peek() {
+                if !(next.split && ifs.contains(next.c)) {
+                    break;
+                }
+                if !is_ifs_space(next.c) {
+                    if hard {
+                        break;
+                    }
+                    hard = true;
+                }
+                chars.next();
+            }
+            if has_content || hard {
+                fields.push(std::mem::take(&mut current));
+            }
+            has_content = false;
+        }
+        if has_content || (fields.is_empty() && self.quoted) {
+            fields.push(current);
+        }
+        fields
+    }
+}
+
+/// Walks the raw text of a single word.
+struct Expander<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    shell: &'a mut Shell,
+    /// Fields already completed by a `"$@"` that stands for several words.
+    fields: Vec<Field>,
+    field: Field,
+    /// In an assignment, a `~` after any unquoted `:` is also expanded, as in
+    /// `PATH=~/bin:~/.cargo/bin`.
+    assignment: bool,
+}
+
+impl<'a> Expander<'a> {
+    fn new(shell: &'a mut Shell, text: &str, assignment: bool) -> Self {
+        Expander { chars: text.chars().collect(), pos: 0, shell, fields: vec![], field: Field::default(), assignment }
+    }
+
+    /// The completed fields, including the one in progress.
+    fn finish(mut self) -> Vec<Field> {
+        self.fields.push(self.field);
+        self.fields
+    }
+
+    /// Ends the current field and starts another, for the gaps between
+    /// positional parameters in `$@`.
+    fn break_field(&mut self, quoted: bool) {
+        let field = std::mem::take(&mut self.field);
+        self.fields.push(field);
+        self.field.quoted = quoted;
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied();
+        self.pos += 1;
+        c
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn run(mut self) -> Result<Vec<Field>, ExpandError> {
+        let mut tilde_allowed = true;
+        while let Some(c) = self.next() {
+            if std::mem::take(&mut tilde_allowed) && c == '~' {
+                self.tilde();
+                continue;
+            }
+            match c {
+                ':' if self.assignment => {
+                    self.field.push(c, false);
+                    tilde_allowed = true;
+                }
+                '\\' => match self.next() {
+                    Some(next) => self.field.push(next, true),
+                    None => self.field.push('\\', false),
+                },
+                '\'' => {
+                    self.field.quoted = true;
+                    while let Some(c) = self.next() {
+                        if c == '\'' {
+                            break;
+                        }
+                        self.field.push(c, true);
+                    }
+                }
+                '"' => self.double_quoted()?,
+                '$' => self.dollar(false)?,
+                '`' => {
+                    let source = self.backquoted_body(false);
+                    self.substitute(&source, false)?;
+                }
+                _ => self.field.push(c, false),
+            }
+        }
+        Ok(self.finish())
+    }
+
+    /// Expands a here-document body: quotes are ordinary characters and a
+    /// backslash only escapes `$`, `` ` ``, `\` and newline.
+    fn here_doc(mut self) -> Result<Vec<Field>, ExpandError> {
+        while let Some(c) = self.next() {
+            match c {
+                '\\' => match self.peek() {
+                    Some(next @ ('\\' | '$' | '`')) => {
+                        self.pos += 1;
+                        self.field.push(next, true);
+                    }
+                    Some('\n') => self.pos += 1,
+                    _ => self.field.push('\\', true),
+                },
+                '$' => self.dollar(true)?,
+                '`' => {
+                    let source = self.backquoted_body(false);
+                    self.substitute(&source, true)?;
+                }
+                _ => self.field.push(c, true),
+            }
+        }
+        Ok(self.finish())
+    }
+
+    fn double_quoted(&mut self) -> Result<(), ExpandError> {
+        let was_quoted = self.field.quoted;
+        let start = (self.fields.len(), self.field.chars.len());
+        let at_empty_params = self.peek() == Some('$')
+            && matches!(self.chars.get(self.pos + 1..self.pos + 3), Some(['@', '"']))
+            && self.shell.positional.is_empty();
+        self.field.quoted = true;
+        while let Some(c) = self.next() {
+            match c {
+                '"' => break,
+                '\\' => match self.peek() {
+                    Some(next @ ('\\' | '"' | '$' | '`')) => {
+                        self.pos += 1;
+                        self.field.push(next, true);
+                    }
+                    Some('\n') => self.pos += 1,
+                    _ => self.field.push('\\', true),
+                },
+                '$' => self.dollar(true)?,
+                '`' => {
+                    let source = self.backquoted_body(true);
+                    self.substitute(&source, true)?;
+                }
+                _ => self.field.push(c, true),
+            }
+        }
+        // `"$@"` with no positional parameters is no word at all
+        if at_empty_params && start == (self.fields.len(), self.field.chars.len()) {
+            self.field.quoted = was_quoted;
+        }
+        Ok(())
+    }
+
+    /// Expands a `~` prefix: `~` and `~user` become home directories, `~+`
+    /// the current directory and `~-` the previous one. A prefix containing
+    /// quotes, or naming an unknown user, stays literal.
+    fn tilde(&mut self) {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == '/' || (c == ':' && self.assignment) {
+                break;
+            }
+            if matches!(c, '\\' | '\'' | '"' | '$' | '`') {
+                self.pos = start;
+                self.field.push('~', false);
+                return;
+            }
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+        let dir = match name.as_str() {
+            "" => self.shell.vars.get("HOME").or_else(|| sys::home_dir(None)),
+            "+" => Some(self.shell.current_dir.to_string_lossy().into_owned()),
+            "-" => self.shell.vars.get("OLDPWD"),
+            user => sys::home_dir(Some(user)),
+        };
+        match dir {
+            Some(dir) => {
+                for c in dir.chars() {
+                    self.field.push(c, true);
+                }
+            }
+            None => {
+                self.field.push('~', false);
+                for c in name.chars() {
+                    self.field.push(c, false);
+                }
+            }
+        }
+    }
+
+    /// Expands what follows a `$`. A `$` that does not start an expansion
+    /// stays literal.
+    fn dollar(&mut self, quoted: bool) -> Result<(), ExpandError> {
+        match self.peek() {
+            Some('(') if self.chars.get(self.pos + 1) != Some(&'(') => {
+                self.pos += 1;
+                let start = self.pos;
+                self.pos = lexer::command_substitution_end(&self.chars, start, self.shell.comments)
+                    .unwrap_or(self.chars.len() + 1);
+                let source: String = self.chars[start..self.pos - 1].iter().collect();
+                self.substitute(&source, quoted)?;
+            }
+            Some('(') => {
+                self.pos += 1;
+                let source = self.bracketed_body('(', ')');
+                match source.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+                    Some(expr) => {
+                        let value = self.shell.eval_arith(expr)?;
+                        self.field.push_expansion(&value.to_string(), quoted);
+                    }
+                    None => self.substitute(&source, quoted)?,
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let body = self.bracketed_body('{', '}');
+                let positional = !body.is_empty() && body.chars().all(|c| c.is_ascii_digit());
+                if !is_name(&body) && !is_special_param(&body) && !positional {
+                    return Err(ExpandError::BadSubstitution(format!("${{{}}}", body)));
+                }
+                self.push_param(&body, quoted);
+            }
+            Some(c) if is_special_param(&c.to_string()) => {
+                self.pos += 1;
+                self.push_param(&c.to_string(), quoted);
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_') {
+                    self.pos += 1;
+                }
+                let name: String = self.chars[start..self.pos].iter().collect();
+                self.push_param(&name, quoted);
+            }
+            _ => self.field.push('$', quoted),
+        }
+        Ok(())
+    }
+
+    /// Expands a parameter into the current field. `$@`, and `$*` outside
+    /// double quotes, give each positional parameter a field of its own;
+    /// `"$*"` joins them with the first character of IFS.
+    fn push_param(&mut self, name: &str, quoted: bool) {
+        if name == "@" || (name == "*" && !quoted) {
+            let params = self.shell.positional.clone();
+            for (i, param) in params.iter().enumerate() {
+                if i > 0 {
+                    self.break_field(quoted);
+                }
+                self.field.push_expansion(param, quoted);
+            }
+            return;
+        }
+        let value = self.param(name).unwrap_or_default();
+        self.field.push_expansion(&value, quoted);
+    }
+
+    /// The value of a variable or special parameter.
+    fn param(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.shell.last_status.to_string()),
+            "#" => Some(self.shell.positional.len().to_string()),
+            "0" => Some(self.shell.arg0.clone()),
+            "@" | "*" => {
+                let separator = match self.shell.vars.get("IFS") {
+                    Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                    None => " ".to_string(),
+                };
+                Some(self.shell.positional.join(&separator))
+            }
+            _ if name.starts_with(|c: char| c.is_ascii_digit()) => {
+                let index: usize = name.parse().ok()?;
+                self.shell.positional.get(index.checked_sub(1)?).cloned()
+            }
+            _ => self.shell.vars.get(name),
+        }
+    }
+
+    /// Reads up to the `close` matching an already consumed `open`, skipping
+    /// over quoted text.
+    fn bracketed_body(&mut self, open: char, close: char) -> String {
+        let start = self.pos;
+        let mut depth = 1;
+        while let Some(c) = self.next() {
+            match c {
+                '\\' => self.pos += 1,
+                '\'' => {
+                    while !matches!(self.next(), Some('\'') | None) {}
+                }
+                '"' | '`' => loop {
+                    match self.next() {
+                        Some('\\') => self.pos += 1,
+                        Some(end) if end == c => break,
+                        None => break,
+                        _ => {}
+                    }
+                },
+                c if c == open => depth += 1,
+                c if c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return self.chars[start..self.pos - 1].iter().collect();
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.chars[start..self.chars.len()].iter().collect()
+    }
+
+    /// Reads the command inside backquotes, where a backslash only escapes
+    /// `$`, `` ` ``, `\` (and `"` inside double quotes).
+    fn backquoted_body(&mut self, in_double_quotes: bool) -> String {
+        let mut source = String::new();
+        while let Some(c) = self.next() {
+            match c {
+                '`' => break,
+                '\\' => match self.next() {
+                    Some(next @ ('$' | '`' | '\\')) => source.push(next),
+                    Some('"') if in_double_quotes => source.push('"'),
+                    Some(next) => {
+                        source.push('\\');
+                        source.push(next);
+                    }
+                    None => source.push('\\'),
+                },
+                _ => source.push(c),
+            }
+        }
+        source
+    }
+
+    /// Runs `source` through the shell and splices in its output, minus any
+    /// trailing newlines.
+    fn substitute(&mut self, source: &str, quoted: bool) -> Result<(), ExpandError> {
+        let list = parser::parse(source, &self.shell.aliases, self.shell.comments)?;
+        let (output, status) = self
+            .shell
+            .capture(&list)
+            .map_err(|err| ExpandError::Substitution(io_error_message(&err)))?;
+        self.shell.last_status = status;
+        self.shell.substitution_status = Some(status);
+        let output = String::from_utf8_lossy(&output);
+        self.field.push_expansion(output.trim_end_matches('\n'), quoted);
+        Ok(())
+    }
+}
+
+/// Parameters with a one-character, non-name spelling such as `$?` and `$1`.
+fn is_special_param(name: &str) -> bool {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => matches!(c, '?' | '#' | '@' | '*') || c.is_ascii_digit(),
+        _ => false,
+    }
+}
+
+/// Joins the fields of a word that is not split, such as an assignment
+/// value, with spaces.
+fn join(fields: Vec<Field>) -> Field {
+    let mut joined = Field::default();
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            joined.push(' ', true);
+        }
+        joined.chars.extend(field.chars);
+        joined.quoted |= field.quoted;
+    }
+    joined
+}
+
+impl Shell {
+    fn expand_fields(&mut self, word: &Word, assignment: bool) -> Result<Vec<Field>, ExpandError> {
+        Expander::new(self, &word.0, assignment).run()
+    }
+
+    /// Expands one word into the fields it stands for, splitting unquoted
+    /// expansions and matching unquoted patterns against the filesystem.
+    pub fn expand_word(&mut self, word: &Word) -> Result<Vec<String>, ExpandError> {
+        let fields = self.expand_fields(word, false)?;
+        let ifs = self.vars.get("IFS").unwrap_or(DEFAULT_IFS.to_string());
+        let mut words = vec![];
+        for field in fields.into_iter().flat_map(|field| field.split(&ifs)) {
+            let pattern = field.pattern();
+            if glob::has_magic(&pattern) {
+                let matches = glob::expand(&pattern, &self.current_dir);
+                if !matches.is_empty() {
+                    words.extend(matches);
+                    continue;
+                }
+            }
+            words.push(field.into_string());
+        }
+        Ok(words)
+    }
+
+    /// Expands command arguments. Unlike `expand_word`, this also performs
+    /// brace expansion, which may turn one word into several.
+    pub fn expand_words(&mut self, words: &[Word]) -> Result<Vec<String>, ExpandError> {
+        let mut fields = vec![];
+        for word in words {
+            for word in brace::expand(&word.0, self.comments) {
+                fields.extend(self.expand_word(&Word(word))?);
+            }
+        }
+        Ok(fields)
+    }
+
+    /// Expands the words of a simple command. Arguments of `export` and
+    /// `local` written as `name=value` are expanded like assignments, into a
+    /// single field with `~` expanded after the `=` and after each `:`.
+    pub fn expand_command_words(&mut self, words: &[Word]) -> Result<Vec<String>, ExpandError> {
+        if !matches!(words.first().map(|word| word.0.as_str()), Some("export" | "local")) {
+            return self.expand_words(words);
+        }
+        let mut fields = self.expand_words(&words[..1])?;
+        for word in &words[1..] {
+            match word.0.split_once('=') {
+                Some((name, value)) if is_name(name) => {
+                    let value = self.expand_assignment(&Word(value.to_string()))?;
+                    fields.push(format!("{}={}", name, value));
+                }
+                _ => fields.extend(self.expand_words(std::slice::from_ref(word))?),
+            }
+        }
+        Ok(fields)
+    }
+
+    /// Expands and evaluates an arithmetic expression, as in `$(( expr ))`.
+    pub fn eval_arith(&mut self, expr: &str) -> Result<i64, ExpandError> {
+        let expr = self.expand_string(&Word(expr.to_string()))?;
+        arith::evaluate(&expr, &mut self.vars).map_err(|err| ExpandError::Arith(expr.trim().to_string(), err))
+    }
+
+    /// Expands a word to a single string without field splitting.
+    pub fn expand_string(&mut self, word: &Word) -> Result<String, ExpandError> {
+        Ok(join(self.expand_fields(word, false)?).into_string())
+    }
+
+    /// Expands a word into a pattern for `case`, without splitting it or
+    /// matching it against files.
+    pub fn expand_pattern(&mut self, word: &Word) -> Result<Vec<PatChar>, ExpandError> {
+        Ok(join(self.expand_fields(word, false)?).pattern())
+    }
+
+    /// Expands the body of a here-document with an unquoted delimiter.
+    pub fn expand_here_doc(&mut self, body: &str) -> Result<String, ExpandError> {
+        Ok(join(Expander::new(self, body, false).here_doc()?).into_string())
+    }
+
+    /// Expands the value of a `name=value` assignment.
+    pub fn expand_assignment(&mut self, word: &Word) -> Result<String, ExpandError> {
+        Ok(join(self.expand_fields(word, true)?).into_string())
+    }
+}
@@ -0,0 +1,256 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A pattern character and whether it was quoted. Quoted characters only
+/// ever match themselves.
+pub type PatChar = (char, bool);
+
+/// Whether `pattern` contains an unquoted `*`, `?` or bracket expression.
+pub fn has_magic(pattern: &[PatChar]) -> bool {
+    pattern.iter().enumerate().any(|(i, &(c, quoted))| match c {
+        '*' | '?' if !quoted => true,
+        '[' if !quoted => bracket(pattern, i, ' ').is_some(),
+        _ => false,
+    })
+}
+
+/// Matches `text` against a shell pattern. `*` and `?` match any character,
+/// including `/` and a leading `.`; pathname expansion enforces those rules
+/// itself.
+pub fn matches(pattern: &[PatChar], text: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // where to resume after the most recent `*`: (pattern index, text index)
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some(('*', false)) => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some(('?', false)) => Some(p + 1),
+            Some(('[', false)) => match bracket(pattern, p, text[t]) {
+                Some((true, next)) => Some(next),
+                Some((false, _)) => None,
+                None if text[t] == '[' => Some(p + 1),
+                None => None,
+            },
+            Some(&(c, _)) if c == text[t] => Some(p + 1),
+            _ => None,
+        };
+        match (step, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                t += 1;
+            }
+            (None, Some((star_p, star_t))) => {
+                p = star_p;
+                t = star_t + 1;
+                backtrack = Some((star_p, star_t + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p.min(pattern.len())..].iter().all(|&pc| pc == ('*', false))
+}
+
+/// Tries the bracket expression starting at `pattern[start]` against `c`.
+/// Returns whether it matched and the index just past the closing `]`, or
+/// `None` if the bracket is never closed and so is an ordinary character.
+fn bracket(pattern: &[PatChar], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = matches!(pattern.get(i), Some(('!', false)) | Some(('^', false)));
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &(pc, quoted) = pattern.get(i)?;
+        if pc == ']' && !quoted && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if pc == '[' && !quoted && pattern.get(i + 1) == Some(&(':', false)) {
+            let rest: String = pattern[i + 2..].iter().map(|&(c, _)| c).collect();
+            if let Some(end) = rest.find(":]") {
+                matched |= class_matches(&rest[..end], c);
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some(('-', false)), Some(&(hi, hi_quoted))) if hi != ']' || hi_quoted => {
+                matched |= pc <= c && c <= hi;
+                i += 3;
+            }
+            _ => {
+                matched |= pc == c;
+                i += 1;
+            }
+        }
+    }
+}
+
+fn class_matches(class: &str, c: char) -> bool {
+    match class {
+        "alnum" => c.is_alphanumeric(),
+        "alpha" => c.is_alphabetic(),
+        "blank" => c == ' ' || c == '\t',
+        "cntrl" => c.is_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        "lower" => c.is_lowercase(),
+        "print" => !c.is_control(),
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_whitespace(),
+        "upper" => c.is_uppercase(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+/// Expands a pathname pattern relative to `cwd`. Returns the sorted matches,
+/// which are empty if nothing matched.
+pub fn expand(pattern: &[PatChar], cwd: &Path) -> Vec<String> {
+    let absolute = pattern.first().map(|&(c, _)| c) == Some('/');
+    // `*/` only matches directories
+    let dirs_only = pattern.last().map(|&(c, _)| c) == Some('/');
+    let components: Vec<&[PatChar]> = pattern.split(|&(c, _)| c == '/').filter(|part| !part.is_empty()).collect();
+    // each candidate is the text shown to the user and the path on disk
+    let mut candidates: Vec<(String, PathBuf)> = if absolute {
+        vec![("/".to_string(), PathBuf::from("/"))]
+    } else {
+        vec![(String::new(), cwd.to_path_buf())]
+    };
+    let last = components.len().saturating_sub(1);
+    for (i, component) in components.iter().enumerate() {
+        let mut next = vec![];
+        let want_dir = i < last || dirs_only;
+        let separator = if want_dir { "/" } else { "" };
+        if !has_magic(component) {
+            let name: String = component.iter().map(|&(c, _)| c).collect();
+            for (shown, path) in candidates {
+                let path = path.join(&name);
+                if path.symlink_metadata().is_ok() {
+                    next.push((format!("{}{}{}", shown, name, separator), path));
+                }
+            }
+        } else {
+            let explicit_dot = component.first().map(|&(c, _)| c) == Some('.');
+            for (shown, path) in candidates {
+                let Ok(entries) = fs::read_dir(&path) else {
+                    continue;
+                };
+                let mut names: Vec<String> = entries
+                    .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                    .filter(|name| explicit_dot || !name.starts_with('.'))
+                    .filter(|name| matches(component, name))
+                    .collect();
+                names.sort();
+                for name in names {
+                    let entry_path = path.join(&name);
+                    if want_dir && !entry_path.is_dir() {
+                        continue;
+                    }
+                    next.push((format!("{}{}{}", shown, name, separator), entry_path));
+                }
+            }
+        }
+        candidates = next;
+    }
+    let mut matches: Vec<String> = candidates.into_iter().map(|(shown, _)| shown).collect();
+    matches.sort();
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a pattern from `s`, with a backslash quoting the character
+    /// after it.
+    fn pat(s: &str) -> Vec<PatChar> {
+        let mut pattern = vec![];
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => pattern.extend(chars.next().map(|c| (c, true))),
+                _ => pattern.push((c, false)),
+            }
+        }
+        pattern
+    }
+
+    fn glob_matches(pattern: &str, text: &str) -> bool {
+        matches(&pat(pattern), text)
+    }
+
+    #[test]
+    fn star_and_question_mark() {
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("*.log", "a.log"));
+        assert!(!glob_matches("*.log", "a.log.1"));
+        assert!(glob_matches("?b", "ab"));
+        assert!(!glob_matches("?b", "b"));
+        assert!(glob_matches("a**b", "ab"));
+    }
+
+    #[test]
+    fn star_backtracks() {
+        assert!(glob_matches("*a*b", "xxaxxb"));
+        assert!(glob_matches("*a*b", "aab"));
+        assert!(glob_matches("*a*b", "abab"));
+        assert!(!glob_matches("*a*b", "xxbxxa"));
+        assert!(glob_matches("*ab", "aaab"));
+        assert!(!glob_matches("*ab", "aaba"));
+    }
+
+    #[test]
+    fn brackets() {
+        assert!(glob_matches("[abc]", "b"));
+        assert!(!glob_matches("[abc]", "d"));
+        assert!(glob_matches("[a-c]x", "bx"));
+        assert!(glob_matches("[!a-c]", "d"));
+        assert!(glob_matches("[^a-c]", "d"));
+        assert!(!glob_matches("[!a-c]", "a"));
+        // a leading `]` is part of the set
+        assert!(glob_matches("[]]", "]"));
+        assert!(glob_matches("[!]]", "a"));
+        assert!(!glob_matches("[!]]", "]"));
+        // a trailing `-` is literal
+        assert!(glob_matches("[a-]", "-"));
+        assert!(glob_matches("[a-]", "a"));
+        assert!(!glob_matches("[a-]", "b"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(glob_matches("[[:digit:]]", "7"));
+        assert!(!glob_matches("[[:digit:]]", "x"));
+        assert!(glob_matches("[[:alpha:]_][[:alnum:]]", "_9"));
+        assert!(glob_matches("[![:space:]]", "x"));
+        assert!(!glob_matches("[![:space:]]", " "));
+    }
+
+    #[test]
+    fn quoted_metacharacters_are_literal() {
+        assert!(glob_matches("\\*", "*"));
+        assert!(!glob_matches("\\*", "a"));
+        assert!(glob_matches("a\\?", "a?"));
+        assert!(!glob_matches("a\\?", "ab"));
+        assert!(glob_matches("\\[ab]", "[ab]"));
+        assert!(!glob_matches("\\[ab]", "a"));
+        assert!(!has_magic(&pat("\\*.log")));
+        assert!(has_magic(&pat("*.log")));
+    }
+
+    #[test]
+    fn unclosed_bracket_is_literal() {
+        assert!(glob_matches("[ab", "[ab"));
+        assert!(!glob_matches("[ab", "a"));
+        assert!(glob_matches("x[", "x["));
+        assert!(!has_magic(&pat("[ab")));
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+use crate::lexer::LexError;
+use crate::parser::{self, ParseError};
+use crate::shell::{Control, Shell};
+use crate::sys;
+
+/// Where the shell reads its commands from.
+pub enum Input {
+    /// The line editor, when stdin is a terminal. Commands read this way go
+    /// into the history.
+    Terminal,
+    /// The lines of a script file or a `-c` string.
+    Lines(VecDeque<String>),
+    /// Stdin when it is not a terminal. It is read a byte at a time, so the
+    /// commands being run still see the rest of the input.
+    Stdin,
+}
+
+impl Input {
+    pub fn from_text(text: &str) -> Self {
+        Input::Lines(text.lines().map(String::from).collect())
+    }
+
+    /// Reads the next line, without its newline. Returns `None` at the end
+    /// of the input.
+    fn read_line(&mut self, shell: &mut Shell, prompt: &str) -> Option<String> {
+        match self {
+            Input::Terminal => {
+                let interactive = shell.interactive;
+                let continuation_prompt = shell.continuation_prompt();
+                shell.line_reader.set_continuation_prompt(continuation_prompt);
+                Some(shell.line_reader.read_line(prompt, interactive))
+            }
+            Input::Lines(lines) => lines.pop_front(),
+            Input::Stdin => {
+                let mut line = vec![];
+                loop {
+                    match sys::read_byte(0) {
+                        Ok(Some(b'\n')) => break,
+                        Ok(Some(byte)) => line.push(byte),
+                        Ok(None) | Err(_) if line.is_empty() => return None,
+                        Ok(None) | Err(_) => break,
+                    }
+                }
+                Some(String::from_utf8_lossy(&line).into_owned())
+            }
+        }
+    }
+}
+
+impl Shell {
+    /// The prompt for the further lines of an incomplete command.
+    fn continuation_prompt(&self) -> String {
+        self.vars.get("PS2").unwrap_or_else(|| String::from("> "))
+    }
+
+    /// Reads and runs commands until the input runs out, `exit` is called or
+    /// a sourced file returns. Each command is parsed once it is complete, so
+    /// at the terminal a syntax error only loses that command, while other
+    /// input stops there with status 2. A command spread over several lines
+    /// goes into the history as a single entry. Returns the status of the
+    /// last command.
+    pub fn run_input(&mut self, input: &mut Input) -> i32 {
+        let saved_comments = self.comments;
+        let status = self.run_commands(input);
+        self.comments = saved_comments;
+        status
+    }
+
+    fn run_commands(&mut self, input: &mut Input) -> i32 {
+        loop {
+            let Some(mut source) = input.read_line(self, "$ ") else {
+                return self.last_status;
+            };
+            if source.trim().is_empty() {
+                continue;
+            }
+            // `interactive_comments` only matters for commands typed at the terminal
+            self.comments = self.interactive_comments || !matches!(input, Input::Terminal);
+            let comments = self.comments;
+            let mut parsed = parser::parse(&source, &self.aliases, comments);
+            while matches!(&parsed, Err(err) if err.is_incomplete()) {
+                let prompt = self.continuation_prompt();
+                let Some(line) = input.read_line(self, &prompt) else {
+                    // a backslash at the very end of the input is kept as is
+                    if matches!(&parsed, Err(ParseError::Lex(LexError::TrailingBackslash))) {
+                        source.push('\\');
+                        parsed = parser::parse(&source, &self.aliases, comments);
+                    }
+                    break;
+                };
+                source.push('\n');
+                source.push_str(&line);
+                parsed = parser::parse(&source, &self.aliases, comments);
+            }
+            if let Input::Terminal = input {
+                let interactive = self.interactive;
+                self.line_reader.insert_history_entry(source.trim(), interactive);
+            }
+            match parsed {
+                Ok(list) => {
+                    self.execute(&list);
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    self.last_status = 2;
+                    // only the terminal gets to carry on after a syntax error
+                    if !matches!(input, Input::Terminal) {
+                        return 2;
+                    }
+                }
+            }
+            if let Some(code) = self.exit_code {
+                return code;
+            }
+            if self.control == Some(Control::Return) {
+                return self.last_status;
+            }
+        }
+    }
+}
@@ -0,0 +1,791 @@
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// A word exactly as typed, quotes and backslashes included. Quote
+    /// removal happens later, during expansion.
+    Word(String),
+    /// The digits in front of a redirection operator, as in `2>`.
+    IoNumber(i32),
+    /// The expression inside a `(( ... ))` command.
+    Arith(String),
+    /// A here-document body, standing in for the delimiter word after `<<`.
+    /// `expand` is false when the delimiter was quoted.
+    HereDoc { body: String, expand: bool },
+    Op(Op),
+    Newline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    AndIf,
+    OrIf,
+    DSemi,
+    DSemiAnd,
+    SemiAnd,
+    DLessDash,
+    TLess,
+    AndDGreat,
+    DLess,
+    DGreat,
+    LessAnd,
+    GreatAnd,
+    LessGreat,
+    AndGreat,
+    Clobber,
+    Pipe,
+    Amp,
+    Semi,
+    Less,
+    Great,
+    LParen,
+    RParen,
+}
+
+/// Every operator the lexer knows, longest first so that `>>` wins over `>`.
+const OPERATORS: &[(&str, Op)] = &[
+    (";;&", Op::DSemiAnd),
+    ("<<-", Op::DLessDash),
+    ("<<<", Op::TLess),
+    ("&>>", Op::AndDGreat),
+    ("&&", Op::AndIf),
+    ("||", Op::OrIf),
+    (";;", Op::DSemi),
+    (";&", Op::SemiAnd),
+    ("<<", Op::DLess),
+    (">>", Op::DGreat),
+    ("<&", Op::LessAnd),
+    (">&", Op::GreatAnd),
+    ("<>", Op::LessGreat),
+    ("&>", Op::AndGreat),
+    (">|", Op::Clobber),
+    ("|", Op::Pipe),
+    ("&", Op::Amp),
+    (";", Op::Semi),
+    ("<", Op::Less),
+    (">", Op::Great),
+    ("(", Op::LParen),
+    (")", Op::RParen),
+];
+
+impl Op {
+    pub fn as_str(&self) -> &'static str {
+        OPERATORS.iter().find(|(_, op)| op == self).map(|(s, _)| *s).unwrap()
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::IoNumber(n) => write!(f, "{}", n),
+            Token::Arith(expr) => write!(f, "(({}))", expr),
+            Token::HereDoc { .. } => write!(f, "here-document"),
+            Token::Op(op) => write!(f, "{}", op.as_str()),
+            Token::Newline => write!(f, "newline"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LexError {
+    #[error("unexpected EOF while looking for matching `{0}'")]
+    Unterminated(char),
+    #[error("here-document delimited by end-of-file (wanted `{0}')")]
+    UnterminatedHereDoc(String),
+    #[error("unexpected EOF after `\\'")]
+    TrailingBackslash,
+}
+
+/// A here-document whose body has not been read yet.
+struct PendingHereDoc {
+    token: usize,
+    delimiter: String,
+    strip_tabs: bool,
+}
+
+fn is_operator_start(c: char) -> bool {
+    matches!(c, '|' | '&' | ';' | '<' | '>' | '(' | ')')
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    /// Whether a `#` that starts a word starts a comment.
+    comments: bool,
+}
+
+impl Lexer {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.peek_at(i) == Some(c))
+    }
+
+    fn operator(&mut self) -> Option<Op> {
+        for (text, op) in OPERATORS {
+            if self.starts_with(text) {
+                self.pos += text.chars().count();
+                return Some(*op);
+            }
+        }
+        None
+    }
+
+    fn word(&mut self) -> Result<String, LexError> {
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\n' => break,
+                c if is_operator_start(c) => break,
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        // line continuation
+                        Some('\n') => self.pos += 1,
+                        Some(next) => {
+                            word.push('\\');
+                            word.push(next);
+                            self.pos += 1;
+                        }
+                        None => return Err(LexError::TrailingBackslash),
+                    }
+                }
+                '\'' => self.single_quoted(&mut word)?,
+                '"' => self.double_quoted(&mut word)?,
+                '$' => self.dollar(&mut word)?,
+                '`' => self.backquoted(&mut word)?,
+                _ => {
+                    word.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        Ok(word)
+    }
+
+    fn single_quoted(&mut self, word: &mut String) -> Result<(), LexError> {
+        word.push('\'');
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                None => return Err(LexError::Unterminated('\'')),
+                Some(c) => {
+                    word.push(c);
+                    self.pos += 1;
+                    if c == '\'' {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn double_quoted(&mut self, word: &mut String) -> Result<(), LexError> {
+        word.push('"');
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                None => return Err(LexError::Unterminated('"')),
+                Some('\\') => {
+                    word.push('\\');
+                    self.pos += 1;
+                    if let Some(next) = self.peek() {
+                        word.push(next);
+                        self.pos += 1;
+                    }
+                }
+                Some('$') => self.dollar(word)?,
+                Some('`') => self.backquoted(word)?,
+                Some(c) => {
+                    word.push(c);
+                    self.pos += 1;
+                    if c == '"' {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Copies a `$` expansion, whose body may contain characters that would
+    /// otherwise end the word, as in `${a}` or `$(ls -l)`.
+    fn dollar(&mut self, word: &mut String) -> Result<(), LexError> {
+        word.push('$');
+        self.pos += 1;
+        match self.peek() {
+            Some('{') => self.nested(word, '{', '}'),
+            Some('(') if self.peek_at(1) == Some('(') => self.nested(word, '(', ')'),
+            Some('(') => {
+                // lexed as commands, so that a `)` ending a `case` pattern
+                // does not end the substitution
+                self.pos += 1;
+                let start = self.pos;
+                self.tokens(true)?;
+                word.push('(');
+                word.extend(&self.chars[start..self.pos]);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the lines of a here-document body up to its delimiter line.
+    fn here_doc_body(&mut self, delimiter: &str, strip_tabs: bool) -> Result<String, LexError> {
+        let mut body = String::new();
+        loop {
+            if self.peek().is_none() {
+                return Err(LexError::UnterminatedHereDoc(delimiter.to_string()));
+            }
+            let start = self.pos;
+            while !matches!(self.peek(), Some('\n') | None) {
+                self.pos += 1;
+            }
+            let mut line: String = self.chars[start..self.pos].iter().collect();
+            let at_end = self.peek().is_none();
+            self.pos += 1;
+            if strip_tabs {
+                line = line.trim_start_matches('\t').to_string();
+            }
+            if line == delimiter {
+                return Ok(body);
+            }
+            if at_end {
+                return Err(LexError::UnterminatedHereDoc(delimiter.to_string()));
+            }
+            body.push_str(&line);
+            body.push('\n');
+        }
+    }
+
+    /// Splits the rest of the input into tokens. In a command substitution,
+    /// stops after the `)` that closes it.
+    fn tokens(&mut self, in_substitution: bool) -> Result<Vec<Token>, LexError> {
+        let mut tokens = vec![];
+        let mut here_docs: Vec<PendingHereDoc> = vec![];
+        let mut nesting = Nesting::default();
+        loop {
+            if nesting.update(&tokens) && in_substitution {
+                tokens.pop();
+                return Ok(tokens);
+            }
+            let Some(c) = self.peek() else {
+                break;
+            };
+            match c {
+                ' ' | '\t' => self.pos += 1,
+                '\n' => {
+                    tokens.push(Token::Newline);
+                    self.pos += 1;
+                    // here-document bodies start on the line after their operator
+                    for here_doc in here_docs.drain(..) {
+                        let body = self.here_doc_body(&here_doc.delimiter, here_doc.strip_tabs)?;
+                        if let Token::HereDoc { body: token_body, .. } = &mut tokens[here_doc.token] {
+                            *token_body = body;
+                        }
+                    }
+                }
+                '(' if self.starts_with("((") && nesting.at_command_start(&tokens) => match self.arith_command() {
+                    Some(expr) => tokens.push(Token::Arith(expr)),
+                    None => {
+                        let op = self.operator().unwrap();
+                        tokens.push(Token::Op(op));
+                    }
+                },
+                '#' if self.comments => {
+                    while matches!(self.peek(), Some(c) if c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                c if is_operator_start(c) => {
+                    let op = self.operator().unwrap();
+                    tokens.push(Token::Op(op));
+                    if op == Op::DLess || op == Op::DLessDash {
+                        while matches!(self.peek(), Some(' ') | Some('\t')) {
+                            self.pos += 1;
+                        }
+                        if matches!(self.peek(), Some(c) if c != '\n' && !is_operator_start(c)) {
+                            let (delimiter, quoted) = here_doc_delimiter(&self.word()?);
+                            here_docs.push(PendingHereDoc { token: tokens.len(), delimiter, strip_tabs: op == Op::DLessDash });
+                            tokens.push(Token::HereDoc { body: String::new(), expand: !quoted });
+                        }
+                    }
+                }
+                _ => {
+                    let word = self.word()?;
+                    if word.is_empty() {
+                        // a lone line continuation
+                        continue;
+                    }
+                    let next_is_redirect = matches!(self.peek(), Some('<') | Some('>'));
+                    match word.parse::<i32>() {
+                        Ok(n) if next_is_redirect && word.bytes().all(|b| b.is_ascii_digit()) => {
+                            tokens.push(Token::IoNumber(n))
+                        }
+                        _ => tokens.push(Token::Word(word)),
+                    }
+                }
+            }
+        }
+        if in_substitution {
+            return Err(LexError::Unterminated(')'));
+        }
+        if let Some(here_doc) = here_docs.first() {
+            return Err(LexError::UnterminatedHereDoc(here_doc.delimiter.clone()));
+        }
+        Ok(tokens)
+    }
+
+    /// Reads `(( expr ))` and returns `expr`. Returns `None`, consuming
+    /// nothing, if the parentheses do not close with `))`, in which case the
+    /// input is two nested subshells instead.
+    fn arith_command(&mut self) -> Option<String> {
+        let start = self.pos;
+        let mut depth = 0;
+        let mut i = start + 2;
+        while let Some(c) = self.chars.get(i).copied() {
+            match c {
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                ')' => {
+                    if self.chars.get(i + 1) != Some(&')') {
+                        return None;
+                    }
+                    self.pos = i + 2;
+                    return Some(self.chars[start + 2..i].iter().collect());
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        None
+    }
+
+    fn backquoted(&mut self, word: &mut String) -> Result<(), LexError> {
+        word.push('`');
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                None => return Err(LexError::Unterminated('`')),
+                Some('\\') => {
+                    word.push('\\');
+                    self.pos += 1;
+                    if let Some(next) = self.peek() {
+                        word.push(next);
+                        self.pos += 1;
+                    }
+                }
+                Some(c) => {
+                    word.push(c);
+                    self.pos += 1;
+                    if c == '`' {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Copies a bracketed region up to and including its matching `close`.
+    fn nested(&mut self, word: &mut String, open: char, close: char) -> Result<(), LexError> {
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                None => return Err(LexError::Unterminated(close)),
+                Some('\\') => {
+                    word.push('\\');
+                    self.pos += 1;
+                    if let Some(next) = self.peek() {
+                        word.push(next);
+                        self.pos += 1;
+                    }
+                }
+                Some('\'') => self.single_quoted(word)?,
+                Some('"') => self.double_quoted(word)?,
+                Some('$') => self.dollar(word)?,
+                Some('`') => self.backquoted(word)?,
+                Some(c) => {
+                    word.push(c);
+                    self.pos += 1;
+                    if c == open {
+                        depth += 1;
+                    } else if c == close {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// How far a `case` command has got, as far as telling the `)` after a
+/// pattern from the one closing a command substitution goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaseState {
+    Subject,
+    In,
+    Patterns,
+    Body,
+}
+
+/// Follows `case` commands and parentheses through the tokens, to tell the
+/// `)` that ends a case pattern, after which a command starts, from the one
+/// that closes a command substitution.
+#[derive(Default)]
+struct Nesting {
+    /// How many of the tokens have been looked at.
+    seen: usize,
+    /// Open parentheses other than those of `case` patterns.
+    depth: usize,
+    cases: Vec<CaseState>,
+    /// Indices of the `)` tokens that end a case pattern.
+    pattern_ends: Vec<usize>,
+}
+
+impl Nesting {
+    /// Looks at the tokens added since the last call. Returns whether the
+    /// last of them is a `)` with no matching `(`, which closes the command
+    /// substitution being lexed, if any.
+    fn update(&mut self, tokens: &[Token]) -> bool {
+        while self.seen < tokens.len() {
+            let before = &tokens[..self.seen];
+            let state = self.cases.last().copied();
+            match (&tokens[self.seen], state) {
+                (Token::Newline, _) => {}
+                (Token::Word(_), Some(CaseState::Subject)) => *self.cases.last_mut().unwrap() = CaseState::In,
+                (Token::Word(word), Some(CaseState::In)) if word == "in" => {
+                    *self.cases.last_mut().unwrap() = CaseState::Patterns
+                }
+                (Token::Word(word), Some(CaseState::Patterns)) if word == "esac" => {
+                    self.cases.pop();
+                }
+                (Token::Word(word), Some(CaseState::Body)) if word == "esac" && self.at_command_start(before) => {
+                    self.cases.pop();
+                }
+                (Token::Op(Op::LParen), Some(CaseState::Patterns)) => {}
+                (Token::Op(Op::RParen), Some(CaseState::Patterns)) => {
+                    *self.cases.last_mut().unwrap() = CaseState::Body;
+                    self.pattern_ends.push(self.seen);
+                }
+                (Token::Op(Op::DSemi | Op::SemiAnd | Op::DSemiAnd), Some(CaseState::Body)) => {
+                    *self.cases.last_mut().unwrap() = CaseState::Patterns
+                }
+                (Token::Word(word), _)
+                    if word == "case" && state != Some(CaseState::Patterns) && self.at_command_start(before) =>
+                {
+                    self.cases.push(CaseState::Subject)
+                }
+                (Token::Op(Op::LParen), _) => self.depth += 1,
+                (Token::Op(Op::RParen), _) if self.depth == 0 => {
+                    self.seen += 1;
+                    return true;
+                }
+                (Token::Op(Op::RParen), _) => self.depth -= 1,
+                _ => {}
+            }
+            self.seen += 1;
+        }
+        false
+    }
+
+    /// Whether the next token would be the first word of a command. A
+    /// reserved word only counts if it was in command position itself.
+    fn at_command_start(&self, tokens: &[Token]) -> bool {
+        match tokens.split_last() {
+            None | Some((Token::Newline, _)) => true,
+            Some((Token::Op(Op::RParen), _)) => self.pattern_ends.contains(&(tokens.len() - 1)),
+            Some((Token::Op(op), _)) => {
+                matches!(op, Op::Semi | Op::AndIf | Op::OrIf | Op::Pipe | Op::Amp | Op::LParen)
+            }
+            // the body of `name() { ... }`
+            Some((Token::Word(word), [.., Token::Word(_), Token::Op(Op::LParen), Token::Op(Op::RParen)]))
+                if word == "{" =>
+            {
+                true
+            }
+            Some((Token::Word(word), rest)) => COMMAND_PREFIXES.contains(&word.as_str()) && self.at_command_start(rest),
+            _ => false,
+        }
+    }
+}
+
+/// Reserved words after which a command (or, for `for`, a `((`) follows.
+const COMMAND_PREFIXES: &[&str] = &["{", "if", "then", "elif", "else", "while", "until", "do", "for"];
+
+/// Removes the quoting from a here-document delimiter. Returns the
+/// delimiter and whether any part of it was quoted.
+fn here_doc_delimiter(word: &str) -> (String, bool) {
+    let mut delimiter = String::new();
+    let mut quoted = false;
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' => quoted = true,
+            '\\' => {
+                quoted = true;
+                delimiter.extend(chars.next());
+            }
+            _ => delimiter.push(c),
+        }
+    }
+    (delimiter, quoted)
+}
+
+/// Splits `input` into words and operators. Operators are recognized
+/// anywhere, so `ls>out` and `a|b` lex the same as their spaced-out forms.
+/// With `comments`, a `#` that starts a word starts a comment running to the
+/// end of the line.
+pub fn tokenize(input: &str, comments: bool) -> Result<Vec<Token>, LexError> {
+    let mut lexer = Lexer { chars: input.chars().collect(), pos: 0, comments };
+    lexer.tokens(false)
+}
+
+/// Finds the end of a command substitution whose body starts at `start`:
+/// the index just past its closing `)`, or `None` if it is not closed.
+/// `comments` is as for `tokenize`.
+pub fn command_substitution_end(chars: &[char], start: usize, comments: bool) -> Option<usize> {
+    let mut lexer = Lexer { chars: chars.to_vec(), pos: start, comments };
+    lexer.tokens(true).ok()?;
+    Some(lexer.pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(input: &str) -> Vec<Token> {
+        tokenize(input, true).unwrap()
+    }
+
+    fn word(s: &str) -> Token {
+        Token::Word(s.to_string())
+    }
+
+    #[test]
+    fn operators_need_no_spaces() {
+        let expected = vec![word("ls"), Token::Op(Op::Great), word("out")];
+        assert_eq!(lex("ls>out"), expected);
+        assert_eq!(lex("ls >out"), expected);
+        assert_eq!(lex("  ls  >   out  "), expected);
+        assert_eq!(lex("a|b"), vec![word("a"), Token::Op(Op::Pipe), word("b")]);
+        assert_eq!(lex("a\t|  b"), vec![word("a"), Token::Op(Op::Pipe), word("b")]);
+    }
+
+    #[test]
+    fn longest_operator_wins() {
+        assert_eq!(
+            lex("ls>>out 2>>err|cat"),
+            vec![
+                word("ls"),
+                Token::Op(Op::DGreat),
+                word("out"),
+                Token::IoNumber(2),
+                Token::Op(Op::DGreat),
+                word("err"),
+                Token::Op(Op::Pipe),
+                word("cat"),
+            ]
+        );
+    }
+
+    #[test]
+    fn io_number_only_directly_before_redirect() {
+        let expected = vec![word("echo"), word("hi"), Token::IoNumber(2), Token::Op(Op::Great), word("err")];
+        assert_eq!(lex("echo hi 2>err"), expected);
+        assert_eq!(lex("echo   hi 2>  err"), expected);
+        assert_eq!(lex("echo 2 >f"), vec![word("echo"), word("2"), Token::Op(Op::Great), word("f")]);
+        assert_eq!(lex("echo a2>f"), vec![word("echo"), word("a2"), Token::Op(Op::Great), word("f")]);
+        assert_eq!(lex("echo 2"), vec![word("echo"), word("2")]);
+    }
+
+    #[test]
+    fn words_keep_their_quoting() {
+        assert_eq!(lex(r#"echo 'a b' "c|d" e\ f"#), vec![word("echo"), word("'a b'"), word("\"c|d\""), word("e\\ f")]);
+    }
+
+    #[test]
+    fn list_operators() {
+        assert_eq!(
+            lex("a&&b||c;d&"),
+            vec![
+                word("a"),
+                Token::Op(Op::AndIf),
+                word("b"),
+                Token::Op(Op::OrIf),
+                word("c"),
+                Token::Op(Op::Semi),
+                word("d"),
+                Token::Op(Op::Amp),
+            ]
+        );
+    }
+
+    #[test]
+    fn substitutions_stay_in_their_word() {
+        assert_eq!(
+            lex("echo $(ls | wc) `a;b`>f"),
+            vec![word("echo"), word("$(ls | wc)"), word("`a;b`"), Token::Op(Op::Great), word("f")]
+        );
+        assert_eq!(lex("echo \"$(echo \")\")\""), vec![word("echo"), word("\"$(echo \")\")\"")]);
+    }
+
+    #[test]
+    fn arith_command_vs_nested_subshells() {
+        assert_eq!(lex("((x = 1 + 2))"), vec![Token::Arith("x = 1 + 2".to_string())]);
+        assert_eq!(
+            lex("if ((x)); then :; fi"),
+            vec![
+                word("if"),
+                Token::Arith("x".to_string()),
+                Token::Op(Op::Semi),
+                word("then"),
+                word(":"),
+                Token::Op(Op::Semi),
+                word("fi"),
+            ]
+        );
+        assert_eq!(
+            lex("((echo a) | cat)"),
+            vec![
+                Token::Op(Op::LParen),
+                Token::Op(Op::LParen),
+                word("echo"),
+                word("a"),
+                Token::Op(Op::RParen),
+                Token::Op(Op::Pipe),
+                word("cat"),
+                Token::Op(Op::RParen),
+            ]
+        );
+        // only in command position
+        assert_eq!(lex("echo (("), vec![word("echo"), Token::Op(Op::LParen), Token::Op(Op::LParen)]);
+    }
+
+    #[test]
+    fn here_doc_body_replaces_delimiter() {
+        assert_eq!(
+            lex("cat <<EOF\nhello $x\nEOF\n"),
+            vec![
+                word("cat"),
+                Token::Op(Op::DLess),
+                Token::HereDoc { body: "hello $x\n".to_string(), expand: true },
+                Token::Newline,
+            ]
+        );
+        assert_eq!(
+            lex("cat <<'EOF' | wc\n$x\nEOF"),
+            vec![
+                word("cat"),
+                Token::Op(Op::DLess),
+                Token::HereDoc { body: "$x\n".to_string(), expand: false },
+                Token::Op(Op::Pipe),
+                word("wc"),
+                Token::Newline,
+            ]
+        );
+        assert_eq!(
+            lex("cat <<-E\n\t\tindented\n\tE\n"),
+            vec![
+                word("cat"),
+                Token::Op(Op::DLessDash),
+                Token::HereDoc { body: "indented\n".to_string(), expand: true },
+                Token::Newline,
+            ]
+        );
+        assert_eq!(lex("cat<<<word"), vec![word("cat"), Token::Op(Op::TLess), word("word")]);
+        assert_eq!(tokenize("cat <<EOF\nno end", true), Err(LexError::UnterminatedHereDoc("EOF".to_string())));
+    }
+
+    #[test]
+    fn dup_redirect() {
+        assert_eq!(lex("cmd 2>&1"), vec![word("cmd"), Token::IoNumber(2), Token::Op(Op::GreatAnd), word("1")]);
+        assert_eq!(lex("cmd >&2"), vec![word("cmd"), Token::Op(Op::GreatAnd), word("2")]);
+        assert_eq!(lex("cmd&>f"), vec![word("cmd"), Token::Op(Op::AndGreat), word("f")]);
+    }
+
+    #[test]
+    fn case_inside_command_substitution() {
+        assert_eq!(
+            lex("x=$(case a in (a) echo ');';; b|c) (echo b);; esac) y"),
+            vec![word("x=$(case a in (a) echo ');';; b|c) (echo b);; esac)"), word("y")]
+        );
+        let chars: Vec<char> = "$(case a in a) echo;; esac)rest".chars().collect();
+        assert_eq!(command_substitution_end(&chars, 2, true), Some(chars.len() - 4));
+    }
+
+    #[test]
+    fn arith_command_after_case_pattern() {
+        assert_eq!(
+            lex("case a in a) ((x=7));; esac"),
+            vec![
+                word("case"),
+                word("a"),
+                word("in"),
+                word("a"),
+                Token::Op(Op::RParen),
+                Token::Arith("x=7".to_string()),
+                Token::Op(Op::DSemi),
+                word("esac"),
+            ]
+        );
+        assert!(lex("case a in a) for ((i=0;i<1;i++)); do :; done;; esac")
+            .contains(&Token::Arith("i=0;i<1;i++".to_string())));
+        // a subshell's `)` is not a pattern's
+        assert_eq!(lex("(a) ((b))")[3], Token::Op(Op::LParen));
+    }
+
+    #[test]
+    fn arith_command_in_function_body() {
+        assert_eq!(
+            lex("f() { ((x=5)); }"),
+            vec![
+                word("f"),
+                Token::Op(Op::LParen),
+                Token::Op(Op::RParen),
+                word("{"),
+                Token::Arith("x=5".to_string()),
+                Token::Op(Op::Semi),
+                word("}"),
+            ]
+        );
+        assert!(lex("f() { for ((i=0;i<2;i++)); do :; done; }").contains(&Token::Arith("i=0;i<2;i++".to_string())));
+        // `{` as an argument is not a function body
+        assert_eq!(lex("echo { ((")[2], Token::Op(Op::LParen));
+    }
+
+    #[test]
+    fn unterminated_input() {
+        assert_eq!(tokenize("echo 'a", true), Err(LexError::Unterminated('\'')));
+        assert_eq!(tokenize("echo \"a", true), Err(LexError::Unterminated('"')));
+        assert_eq!(tokenize("echo $(ls", true), Err(LexError::Unterminated(')')));
+        assert_eq!(tokenize("echo a \\", true), Err(LexError::TrailingBackslash));
+        assert_eq!(lex("echo a \\\nb"), vec![word("echo"), word("a"), word("b")]);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(lex("echo a#b # note"), vec![word("echo"), word("a#b")]);
+        assert_eq!(lex("echo '#' \\# $#"), vec![word("echo"), word("'#'"), word("\\#"), word("$#")]);
+        assert_eq!(lex("a;# note\nb"), vec![word("a"), Token::Op(Op::Semi), Token::Newline, word("b")]);
+        assert_eq!(tokenize("echo # note", false).unwrap(), vec![word("echo"), word("#"), word("note")]);
+    }
+
+    #[test]
+    fn comments_in_command_substitution() {
+        assert_eq!(lex("echo $(a # )\n)"), vec![word("echo"), word("$(a # )\n)")]);
+        assert_eq!(tokenize("echo $(a # ) b", false).unwrap(), vec![word("echo"), word("$(a # )"), word("b")]);
+        let chars: Vec<char> = "$(a # )\n)".chars().collect();
+        assert_eq!(command_substitution_end(&chars, 2, true), Some(chars.len()));
+        assert_eq!(command_substitution_end(&chars, 2, false), Some(7));
+    }
+}
@@ -0,0 +1,361 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use crossterm::terminal::{enable_raw_mode, disable_raw_mode};
+
+pub struct LineBuffer {
+    buf: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    history_cursor: usize,
+    builtins: Vec<String>,
+    aliases: Vec<String>,
+    hints: Vec<String>,
+    in_tab_completion: bool,
+    continuation_prompt: String,
+    /// The line of the buffer the terminal cursor is on, counted from the
+    /// one with the prompt.
+    cursor_row: usize,
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        Self { buf: vec![], cursor: 0, history: vec![], history_cursor: 0, builtins: vec![], aliases: vec![], hints: vec![], in_tab_completion: false, continuation_prompt: String::from("> "), cursor_row: 0 }
+    }
+
+    fn clear_hints(&mut self) {
+        self.hints = vec![];
+        self.in_tab_completion = false;
+    }
+
+    pub fn set_builtins(&mut self, builtins: &[&str]) {
+        for builtin in builtins {
+            let builtin = String::from(*builtin);
+            self.builtins.push(builtin);
+        }
+    }
+
+    /// Replaces the alias names offered by tab completion.
+    pub fn set_aliases(&mut self, aliases: Vec<String>) {
+        self.aliases = aliases;
+    }
+
+    /// Sets the prompt shown in front of the further lines of a multi-line
+    /// command.
+    pub fn set_continuation_prompt(&mut self, prompt: String) {
+        self.continuation_prompt = prompt;
+    }
+
+    fn clear(&mut self) {
+        self.buf = vec![];
+        self.cursor = 0;
+    }
+
+    fn insert(&mut self, c: char) {
+        self.buf.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    pub fn insert_history_entry(&mut self, entry: &str, interactive: bool) {
+        if self.history.is_empty() || entry != self.history.last().unwrap() || !interactive {
+            self.history.push(String::from(entry));
+            self.history_cursor = self.history.len();
+        }
+    }
+
+    pub fn get_history(&self) -> Vec<String> {
+        self.history.clone()
+    }
+
+    fn delete_left(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.buf.remove(self.cursor);
+        }
+    }
+
+    fn delete_right(&mut self) {
+        if self.cursor < self.buf.len() {
+            self.buf.remove(self.cursor);
+        }
+    }
+
+    fn move_left(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+        }
+    }
+
+    fn move_right(&mut self) {
+        if self.cursor < self.buf.len() {
+            self.cursor += 1;
+        }
+    }
+
+    fn tab_completion(&mut self) {
+        let mut potential = vec![];
+        for builtin in self.builtins.iter().chain(&self.aliases) {
+            if builtin.contains(&self.buf.iter().collect::<String>()) {
+                potential.push(String::from(builtin));
+            }
+        }
+        if potential.is_empty() {
+            let hints = find_executable_hints(&self.buf.iter().collect::<String>());
+            for hint in hints {
+                let path = PathBuf::from(hint);
+                potential.push(String::from(path.file_name().unwrap().to_str().unwrap()));
+            }
+        }
+        potential.sort();
+        potential.dedup();
+        if potential.len() == 1 {
+            let mut to_complete = String::from(&potential[0]);
+            to_complete.push(' ');
+            self.buf = to_complete.chars().collect();
+            self.cursor = self.buf.len();
+        } else {
+            print!("\x07");
+            io::stdout().flush().unwrap();
+            if potential.len() > 1 {
+                let common_prefix = find_common_prefix(&potential);
+                if common_prefix != self.buf.iter().collect::<String>() {
+                    self.buf = common_prefix.chars().collect();
+                    self.cursor = self.buf.len();
+                }
+                self.hints = potential;
+                self.in_tab_completion = true;
+            }
+        }
+    }
+
+    fn tab_hints(&mut self) {
+        println!("\n\r\x1B[K{}", self.hints.join("  "));
+        self.cursor_row = 0;
+        self.clear_hints();
+    }
+
+    fn move_up_history(&mut self) {
+        if self.history_cursor > 0 {
+            let at_end = self.cursor == self.buf.len();
+            self.history_cursor -= 1;
+            self.buf = self.history[self.history_cursor].chars().collect();
+            if self.cursor > self.buf.len() || at_end {
+                self.cursor = self.buf.len();
+            }
+        }
+    }
+
+    fn move_down_history(&mut self) {
+        if self.history_cursor < self.history.len() {
+            let at_end = self.cursor == self.buf.len();
+            self.history_cursor += 1;
+            if self.history_cursor == self.history.len() {
+                self.buf = vec![];
+            } else {
+                self.buf = self.history[self.history_cursor].chars().collect();
+            }
+            if self.cursor > self.buf.len() || at_end {
+                self.cursor = self.buf.len();
+            }
+        }
+    }
+
+    /// Redraws the buffer, which may hold a multi-line command recalled from
+    /// the history, with the continuation prompt in front of its later lines.
+    fn render(&mut self, prompt: &str) {
+        if self.cursor_row > 0 {
+            print!("\x1B[{}A", self.cursor_row);
+        }
+        print!("\r\x1B[J");
+        let text = self.to_str();
+        for (i, line) in text.split('\n').enumerate() {
+            if i == 0 {
+                print!("{}{}", prompt, line);
+            } else {
+                print!("\r\n{}{}", self.continuation_prompt, line);
+            }
+        }
+        let before_cursor = &self.buf[..self.cursor];
+        let row = before_cursor.iter().filter(|&&c| c == '\n').count();
+        let column = before_cursor.iter().rev().take_while(|&&c| c != '\n').count();
+        let rows_below = text.matches('\n').count() - row;
+        if rows_below > 0 {
+            print!("\x1B[{}A", rows_below);
+        }
+        let prompt_width = if row == 0 { prompt } else { &self.continuation_prompt }.chars().count();
+        print!("\r");
+        if prompt_width + column > 0 {
+            print!("\x1B[{}C", prompt_width + column);
+        }
+        self.cursor_row = row;
+        io::stdout().flush().unwrap();
+    }
+
+    pub fn read_line(&mut self, prompt: &str, interactive: bool) -> String {
+        self.clear_hints();
+        if interactive {
+            print!("\r\x1B[K{}", prompt);
+        } else {
+            print!("{}", prompt)
+        }
+        io::stdout().flush().unwrap();
+        enable_raw_mode().unwrap();
+        self.clear();
+        self.cursor_row = 0;
+        loop {
+            let key = read_key();
+            match key.as_str() {
+                "\r" => break,
+                "\n" => break,
+                "left" => self.move_left(),
+                "right" => self.move_right(),
+                "up" => self.move_up_history(),
+                "down" => self.move_down_history(),
+                "\x7F" => self.delete_left(),
+                "\x09" => {
+                    if self.in_tab_completion {
+                        self.tab_hints();
+                    } else {
+                        self.tab_completion()
+                    }
+                },
+                "delete" => self.delete_right(),
+                s if s.len() == 1 => self.insert(s.chars().next().unwrap()),
+                _ => {}
+            }
+            if interactive {
+                self.render(prompt);
+            } else if key == "up" || key == "down" || key == "\x09" {
+                print!("\r\x1B[K{}", prompt);
+                print!("{}", self.to_str());
+                io::stdout().flush().unwrap();
+            } else {
+                print!("{}", key);
+                io::stdout().flush().unwrap();
+            }
+            if key != "\x09" {
+                self.clear_hints();
+            }
+        }
+
+        if interactive && self.cursor < self.buf.len() {
+            // leave the terminal cursor below the whole command
+            self.cursor = self.buf.len();
+            self.render(prompt);
+        }
+        self.history_cursor = self.history.len();
+        disable_raw_mode().unwrap();
+        println!();
+        self.to_str()
+    }
+
+    fn to_str(&self) -> String {
+        self.buf.iter().collect::<String>()
+    }
+}
+
+/// Formats a history entry for the history file, newline included. The
+/// later lines of a multi-line command are indented by a tab, which no entry
+/// starts with since commands are trimmed before they go into the history.
+pub fn history_file_entry(entry: &str) -> String {
+    format!("{}\n", entry.replace('\n', "\n\t"))
+}
+
+/// Splits the contents of a history file back into entries, joining the
+/// lines of multi-line commands.
+pub fn history_file_entries(contents: &str) -> Vec<String> {
+    let mut entries: Vec<String> = vec![];
+    for line in contents.split('\n') {
+        match (line.strip_prefix('\t'), entries.last_mut()) {
+            (Some(rest), Some(entry)) => {
+                entry.push('\n');
+                entry.push_str(rest);
+            }
+            _ if line.is_empty() => {}
+            _ => entries.push(line.to_string()),
+        }
+    }
+    entries
+}
+
+fn find_common_prefix(hints: &[String]) -> String {
+    if hints.is_empty() {
+        "".into()
+    } else if hints.len() == 1 {
+        hints[0].clone()
+    } else {
+        let mut common_prefix = String::from(&hints[0]);
+        for hint in hints[1..].iter() {
+            if !hint.contains(&common_prefix) {
+                let mut new_common_prefix = String::new();
+                for (c1, c2) in hint.chars().zip(common_prefix.chars()) {
+                    if c1 == c2 {
+                        new_common_prefix.push(c1);
+                    } else {
+                        break;
+                    }
+                }
+                common_prefix = new_common_prefix;
+            }
+        }
+        common_prefix
+    }
+}
+
+fn find_executable_hints(executable_name: &str) -> Vec<String> {
+    let path_var = env::var("PATH").unwrap();
+    let mut hints_found = vec![];
+    for dir_name in path_var.split(":") {
+        let dir_path = PathBuf::from(dir_name);
+        if !dir_path.exists() {
+            continue;
+        }
+        for entry in fs::read_dir(dir_path).unwrap() {
+            let entry = entry.unwrap();
+            let path = entry.path();
+            if path.file_name().unwrap().to_str().unwrap().starts_with(executable_name) {
+                let metadata = fs::metadata(&path).unwrap();
+                let permissions = metadata.permissions();
+                let mode = permissions.mode() as u16;
+                let executable = 493u16;
+                let is_executable = (mode & executable) == executable;
+                if is_executable {
+                    hints_found.push(String::from(path.to_str().unwrap()));
+                }
+            }
+        }
+    }
+    hints_found
+}
+
+fn read_key() -> String {
+    let mut stdin = std::io::stdin();
+    let mut buf = [0; 3];
+    stdin.read_exact(&mut buf[..1]).unwrap();
+
+    if buf[0] == 0x1B {
+        // Possible escape sequence
+        if stdin.read(&mut buf[1..]).unwrap_or(0) == 2 {
+            match &buf {
+                [0x1B, 0x5B, 0x41] => return "up".into(),
+                [0x1B, 0x5B, 0x42] => return "down".into(),
+                [0x1B, 0x5B, 0x43] => return "right".into(),
+                [0x1B, 0x5B, 0x44] => return "left".into(),
+                [0x1B, 0x5B, 0x33] => {
+                    stdin.read_exact(&mut buf[..1]).unwrap();
+                    if buf[0] == 0x7E {
+                        return "delete".into()
+                    } else {
+                        return "escape".into()
+                    }
+                },
+                _ => return "escape".into(),
+            }
+        } else {
+            return "escape".into();
+        }
+    }
+    (buf[0] as char).to_string()
+}
@@ -0,0 +1,660 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use crate::ast::{
+    AndOr, AndOrOp, Assignment, CaseItem, CaseTerminator, Command, CompoundCommand, List, Pipeline, Redirect, RedirectKind,
+    SimpleCommand, Word,
+};
+use crate::lexer::{self, LexError, Op, Token};
+use crate::variables::is_name;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseError {
+    #[error(transparent)]
+    Lex(#[from] LexError),
+    #[error("syntax error near unexpected token `{0}'")]
+    Unexpected(String),
+    #[error("syntax error: unexpected end of file")]
+    UnexpectedEof,
+}
+
+impl ParseError {
+    /// Whether the input ended early and could be completed by reading more
+    /// lines.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            ParseError::Lex(
+                LexError::Unterminated(_) | LexError::UnterminatedHereDoc(_) | LexError::TrailingBackslash
+            ) | ParseError::UnexpectedEof
+        )
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    aliases: &'a BTreeMap<String, String>,
+    /// Aliases whose expansion is being parsed, with the index just past
+    /// their tokens, so that an alias is not expanded inside its own value.
+    expanding: Vec<(String, usize)>,
+    /// Where the word after an alias whose value ends in a blank sits. That
+    /// word is checked for an alias too.
+    alias_next: Option<usize>,
+    /// Whether `#` starts a comment, here and in alias values.
+    comments: bool,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(token) => ParseError::Unexpected(token.to_string()),
+            None => ParseError::UnexpectedEof,
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    /// Whether the next token is a reserved word.
+    fn at_word(&self, reserved: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word == reserved)
+    }
+
+    /// Consumes `expected`, or fails on whatever is there instead.
+    fn expect(&mut self, expected: &Token) -> Result<(), ParseError> {
+        if self.peek() != Some(expected) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Whether the next token closes the list being parsed. Reserved words
+    /// only count where a command could start, which is the only place this
+    /// is asked.
+    fn at_list_end(&self) -> bool {
+        match self.peek() {
+            None | Some(Token::Op(Op::RParen | Op::DSemi | Op::SemiAnd | Op::DSemiAnd)) => true,
+            Some(Token::Word(word)) => RESERVED_CLOSERS.contains(&word.as_str()),
+            _ => false,
+        }
+    }
+
+    /// Parses commands up to the end of the input or a token that closes an
+    /// enclosing construct, which is left for the caller.
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut list = List::default();
+        self.skip_newlines();
+        while !self.at_list_end() {
+            list.items.push(self.and_or()?);
+            match self.peek() {
+                Some(Token::Newline) => self.skip_newlines(),
+                Some(Token::Op(Op::Semi)) => {
+                    self.pos += 1;
+                    self.skip_newlines();
+                }
+                _ if self.at_list_end() => break,
+                _ => return Err(self.unexpected()),
+            }
+        }
+        Ok(list)
+    }
+
+    /// Parses a non-empty list closed by `close`.
+    fn compound_list(&mut self, close: &Token) -> Result<List, ParseError> {
+        let list = self.list()?;
+        if list.items.is_empty() {
+            return Err(self.unexpected());
+        }
+        self.expect(close)?;
+        Ok(list)
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = vec![];
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(Op::AndIf)) => AndOrOp::And,
+                Some(Token::Op(Op::OrIf)) => AndOrOp::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            rest.push((op, self.pipeline()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut commands = vec![self.command()?];
+        while self.peek() == Some(&Token::Op(Op::Pipe)) {
+            self.pos += 1;
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { commands })
+    }
+
+    /// Replaces the word at the current position with the tokens of its
+    /// alias, if it has one. Returns whether it did.
+    fn expand_alias(&mut self) -> Result<bool, ParseError> {
+        let pos = self.pos;
+        self.expanding.retain(|(_, end)| *end > pos);
+        let Some(Token::Word(name)) = self.peek() else {
+            return Ok(false);
+        };
+        if self.expanding.iter().any(|(expanding, _)| expanding == name) {
+            return Ok(false);
+        }
+        let Some(value) = self.aliases.get(name) else {
+            return Ok(false);
+        };
+        let name = name.clone();
+        let tokens = lexer::tokenize(value, self.comments)?;
+        let count = tokens.len();
+        self.tokens.splice(pos..pos + 1, tokens);
+        // every alias still being expanded contains the word just replaced
+        for (_, end) in &mut self.expanding {
+            *end = *end + count - 1;
+        }
+        self.expanding.push((name, pos + count));
+        self.alias_next = value.ends_with([' ', '\t']).then_some(pos + count);
+        Ok(true)
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        while self.expand_alias()? {}
+        if let Some(Token::Arith(expr)) = self.peek() {
+            let expr = Word(expr.clone());
+            self.pos += 1;
+            return Ok(Command::Arith(expr));
+        }
+        if let (Some(Token::Word(name)), Some(Token::Op(Op::LParen)), Some(Token::Op(Op::RParen))) =
+            (self.peek(), self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2))
+        {
+            if is_name(name) {
+                let name = name.clone();
+                self.pos += 3;
+                self.skip_newlines();
+                let starts_compound = match self.peek() {
+                    Some(Token::Op(Op::LParen)) => true,
+                    Some(Token::Word(word)) => COMPOUND_STARTS.contains(&word.as_str()),
+                    _ => false,
+                };
+                if !starts_compound {
+                    return Err(self.unexpected());
+                }
+                let body = self.command()?;
+                return Ok(Command::FunctionDef { name, body: Rc::new(body) });
+            }
+        }
+        let compound = if self.peek() == Some(&Token::Op(Op::LParen)) {
+            self.pos += 1;
+            Some(CompoundCommand::Subshell(self.compound_list(&Token::Op(Op::RParen))?))
+        } else if self.at_word("{") {
+            self.pos += 1;
+            Some(CompoundCommand::Group(self.compound_list(&Token::Word("}".into()))?))
+        } else if self.at_word("if") {
+            Some(self.if_command()?)
+        } else if self.at_word("while") || self.at_word("until") {
+            let until = self.at_word("until");
+            self.pos += 1;
+            let condition = self.compound_list(&Token::Word("do".into()))?;
+            let body = self.compound_list(&Token::Word("done".into()))?;
+            Some(CompoundCommand::While { condition, body, until })
+        } else if self.at_word("for") {
+            Some(self.for_command()?)
+        } else if self.at_word("case") {
+            Some(self.case_command()?)
+        } else {
+            None
+        };
+        if let Some(compound) = compound {
+            let mut redirects = vec![];
+            while self.peek().is_some_and(is_redirect_start) {
+                redirects.push(self.redirect()?);
+            }
+            return Ok(Command::Compound(compound, redirects));
+        }
+        let mut command = SimpleCommand::default();
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    let word = word.clone();
+                    let is_command_word = command.words.is_empty() && assignment(&word).is_none();
+                    if (is_command_word || self.alias_next == Some(self.pos)) && self.expand_alias()? {
+                        continue;
+                    }
+                    match assignment(&word) {
+                        Some(assignment) if command.words.is_empty() => command.assignments.push(assignment),
+                        _ => command.words.push(Word(word)),
+                    }
+                    self.pos += 1;
+                }
+                Some(token) if is_redirect_start(token) => command.redirects.push(self.redirect()?),
+                _ => break,
+            }
+        }
+        if command.assignments.is_empty() && command.words.is_empty() && command.redirects.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(Command::Simple(command))
+    }
+
+    fn if_command(&mut self) -> Result<CompoundCommand, ParseError> {
+        let mut branches = vec![];
+        let mut else_body = None;
+        // consumes `if`, then any `elif`s
+        loop {
+            self.pos += 1;
+            let condition = self.compound_list(&Token::Word("then".into()))?;
+            let body = self.list()?;
+            if body.items.is_empty() {
+                return Err(self.unexpected());
+            }
+            branches.push((condition, body));
+            if !self.at_word("elif") {
+                break;
+            }
+        }
+        if self.at_word("else") {
+            self.pos += 1;
+            let body = self.list()?;
+            if body.items.is_empty() {
+                return Err(self.unexpected());
+            }
+            else_body = Some(body);
+        }
+        self.expect(&Token::Word("fi".into()))?;
+        Ok(CompoundCommand::If { branches, else_body })
+    }
+
+    fn for_command(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.pos += 1;
+        if let Some(Token::Arith(expr)) = self.peek() {
+            let parts: Vec<&str> = expr.split(';').collect();
+            let [init, condition, step] = parts.as_slice() else {
+                return Err(ParseError::Unexpected(format!("(({}))", expr)));
+            };
+            let (init, condition, step) = (Word(init.to_string()), Word(condition.to_string()), Word(step.to_string()));
+            self.pos += 1;
+            if self.peek() == Some(&Token::Op(Op::Semi)) {
+                self.pos += 1;
+            }
+            self.skip_newlines();
+            let body = self.do_group()?;
+            return Ok(CompoundCommand::ArithFor { init, condition, step, body });
+        }
+        let name = match self.peek() {
+            Some(Token::Word(word)) if is_name(word) => word.clone(),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        self.skip_newlines();
+        let mut words = None;
+        if self.at_word("in") {
+            self.pos += 1;
+            let mut list = vec![];
+            while let Some(Token::Word(word)) = self.peek() {
+                list.push(Word(word.clone()));
+                self.pos += 1;
+            }
+            words = Some(list);
+            match self.peek() {
+                Some(Token::Op(Op::Semi)) | Some(Token::Newline) => self.pos += 1,
+                _ => return Err(self.unexpected()),
+            }
+        } else if self.peek() == Some(&Token::Op(Op::Semi)) {
+            self.pos += 1;
+        }
+        self.skip_newlines();
+        let body = self.do_group()?;
+        Ok(CompoundCommand::For { name, words, body })
+    }
+
+    fn case_command(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.pos += 1;
+        let word = match self.next() {
+            Some(Token::Word(word)) => Word(word),
+            Some(token) => return Err(ParseError::Unexpected(token.to_string())),
+            None => return Err(ParseError::UnexpectedEof),
+        };
+        self.skip_newlines();
+        self.expect(&Token::Word("in".into()))?;
+        self.skip_newlines();
+        let mut items = vec![];
+        while !self.at_word("esac") {
+            if self.peek() == Some(&Token::Op(Op::LParen)) {
+                self.pos += 1;
+            }
+            let mut patterns = vec![];
+            loop {
+                match self.next() {
+                    Some(Token::Word(word)) => patterns.push(Word(word)),
+                    Some(token) => return Err(ParseError::Unexpected(token.to_string())),
+                    None => return Err(ParseError::UnexpectedEof),
+                }
+                if self.peek() != Some(&Token::Op(Op::Pipe)) {
+                    break;
+                }
+                self.pos += 1;
+            }
+            self.expect(&Token::Op(Op::RParen))?;
+            let body = self.list()?;
+            let terminator = match self.peek() {
+                Some(Token::Op(Op::DSemi)) => CaseTerminator::Break,
+                Some(Token::Op(Op::SemiAnd)) => CaseTerminator::FallThrough,
+                Some(Token::Op(Op::DSemiAnd)) => CaseTerminator::Continue,
+                _ if self.at_word("esac") => CaseTerminator::Break,
+                _ => return Err(self.unexpected()),
+            };
+            if !self.at_word("esac") {
+                self.pos += 1;
+            }
+            self.skip_newlines();
+            items.push(CaseItem { patterns, body, terminator });
+        }
+        self.pos += 1;
+        Ok(CompoundCommand::Case { word, items })
+    }
+
+    /// Parses `do list done`.
+    fn do_group(&mut self) -> Result<List, ParseError> {
+        self.expect(&Token::Word("do".into()))?;
+        self.compound_list(&Token::Word("done".into()))
+    }
+
+    fn redirect(&mut self) -> Result<Redirect, ParseError> {
+        let fd = match self.peek() {
+            Some(Token::IoNumber(n)) => {
+                let n = *n;
+                self.pos += 1;
+                Some(n)
+            }
+            _ => None,
+        };
+        let (kind, default_fd) = match self.peek() {
+            Some(Token::Op(Op::Great | Op::Clobber)) => (RedirectKind::Write, 1),
+            Some(Token::Op(Op::DGreat)) => (RedirectKind::Append, 1),
+            Some(Token::Op(Op::AndGreat)) => (RedirectKind::WriteAll, 1),
+            Some(Token::Op(Op::AndDGreat)) => (RedirectKind::AppendAll, 1),
+            Some(Token::Op(Op::Less)) => (RedirectKind::Read, 0),
+            Some(Token::Op(Op::LessGreat)) => (RedirectKind::ReadWrite, 0),
+            Some(Token::Op(Op::LessAnd)) => (RedirectKind::DupRead, 0),
+            Some(Token::Op(Op::GreatAnd)) => (RedirectKind::DupWrite, 1),
+            Some(Token::Op(Op::TLess)) => (RedirectKind::HereString, 0),
+            Some(Token::Op(Op::DLess | Op::DLessDash)) => {
+                self.pos += 1;
+                return match self.next() {
+                    Some(Token::HereDoc { body, expand }) => Ok(Redirect {
+                        fd: fd.unwrap_or(0),
+                        kind: RedirectKind::HereDoc { expand },
+                        target: Word(body),
+                    }),
+                    Some(token) => Err(ParseError::Unexpected(token.to_string())),
+                    None => Err(ParseError::Unexpected("newline".into())),
+                };
+            }
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        let target = match self.peek() {
+            Some(Token::Word(_)) => match self.next() {
+                Some(Token::Word(word)) => Word(word),
+                _ => unreachable!(),
+            },
+            Some(_) => return Err(self.unexpected()),
+            None => return Err(ParseError::Unexpected("newline".into())),
+        };
+        Ok(Redirect { fd: fd.unwrap_or(default_fd), kind, target })
+    }
+}
+
+/// Reserved words that start a compound command.
+const COMPOUND_STARTS: &[&str] = &["{", "if", "while", "until", "for", "case"];
+
+/// Reserved words that end a list when they appear in command position.
+const RESERVED_CLOSERS: &[&str] = &["}", "then", "elif", "else", "fi", "do", "done", "esac"];
+
+fn is_redirect_start(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::IoNumber(_)
+            | Token::Op(
+                Op::Great
+                    | Op::DGreat
+                    | Op::Clobber
+                    | Op::AndGreat
+                    | Op::AndDGreat
+                    | Op::Less
+                    | Op::LessGreat
+                    | Op::LessAnd
+                    | Op::GreatAnd
+                    | Op::DLess
+                    | Op::DLessDash
+                    | Op::TLess
+            )
+    )
+}
+
+/// Splits `NAME=value` into its parts, if `word` has that form.
+fn assignment(word: &str) -> Option<Assignment> {
+    let (name, value) = word.split_once('=')?;
+    if !is_name(name) {
+        return None;
+    }
+    Some(Assignment { name: name.to_string(), value: Word(value.to_string()) })
+}
+
+/// Parses a complete chunk of input into a command list, expanding the
+/// given aliases and, with `comments`, dropping comments.
+pub fn parse(input: &str, aliases: &BTreeMap<String, String>, comments: bool) -> Result<List, ParseError> {
+    let tokens = lexer::tokenize(input, comments)?;
+    let mut parser = Parser { tokens, pos: 0, aliases, expanding: vec![], alias_next: None, comments };
+    let list = parser.list()?;
+    match parser.peek() {
+        None => Ok(list),
+        Some(_) => Err(parser.unexpected()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_list(input: &str) -> List {
+        parse(input, &BTreeMap::new(), true).unwrap()
+    }
+
+    /// The commands of `input`, which must be a single pipeline.
+    fn pipeline(input: &str) -> Vec<Command> {
+        let list = parse_list(input);
+        assert_eq!(list.items.len(), 1);
+        assert!(list.items[0].rest.is_empty());
+        list.items[0].first.commands.clone()
+    }
+
+    /// `input`, which must be a single command.
+    fn command(input: &str) -> Command {
+        let mut commands = pipeline(input);
+        assert_eq!(commands.len(), 1);
+        commands.remove(0)
+    }
+
+    fn words(words: &[&str]) -> Vec<Word> {
+        words.iter().map(|word| Word(word.to_string())).collect()
+    }
+
+    fn simple(command: &[&str], redirects: Vec<Redirect>) -> Command {
+        Command::Simple(SimpleCommand { assignments: vec![], words: words(command), redirects })
+    }
+
+    fn redirect(fd: i32, kind: RedirectKind, target: &str) -> Redirect {
+        Redirect { fd, kind, target: Word(target.to_string()) }
+    }
+
+    fn error(input: &str) -> ParseError {
+        parse(input, &BTreeMap::new(), true).unwrap_err()
+    }
+
+    #[test]
+    fn redirects_with_odd_spacing() {
+        let expected = simple(&["ls"], vec![redirect(1, RedirectKind::Write, "out")]);
+        assert_eq!(command("ls>out"), expected);
+        assert_eq!(command("ls >  out"), expected);
+        let expected = simple(&["echo", "hi"], vec![redirect(2, RedirectKind::Write, "err")]);
+        assert_eq!(command("echo hi 2>err"), expected);
+        assert_eq!(command("  echo hi    2>   err "), expected);
+        assert_eq!(command("2>err echo hi"), expected);
+        assert_eq!(command("cmd 2>>log"), simple(&["cmd"], vec![redirect(2, RedirectKind::Append, "log")]));
+    }
+
+    #[test]
+    fn io_number_vs_word() {
+        assert_eq!(command("echo 2 >f"), simple(&["echo", "2"], vec![redirect(1, RedirectKind::Write, "f")]));
+        assert_eq!(command("echo a2>f"), simple(&["echo", "a2"], vec![redirect(1, RedirectKind::Write, "f")]));
+    }
+
+    #[test]
+    fn pipelines() {
+        assert_eq!(pipeline("a|b"), vec![simple(&["a"], vec![]), simple(&["b"], vec![])]);
+        assert_eq!(pipeline("a | b|c").len(), 3);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("echo >"), ParseError::Unexpected("newline".to_string()));
+        assert_eq!(error("| a"), ParseError::Unexpected("|".to_string()));
+        assert_eq!(error("a |"), ParseError::UnexpectedEof);
+    }
+
+    #[test]
+    fn lists() {
+        let list = parse_list("a && b || c; d\ne");
+        assert_eq!(list.items.len(), 3);
+        let ops: Vec<AndOrOp> = list.items[0].rest.iter().map(|(op, _)| *op).collect();
+        assert_eq!(ops, vec![AndOrOp::And, AndOrOp::Or]);
+        assert_eq!(list.items[1].first.commands, vec![simple(&["d"], vec![])]);
+        assert_eq!(parse_list("a;").items.len(), 1);
+        assert_eq!(error("; a"), ParseError::Unexpected(";".to_string()));
+    }
+
+    #[test]
+    fn assignments_before_words() {
+        let Command::Simple(simple) = command("a=1 b=\"x y\" env c=2") else {
+            panic!("not a simple command");
+        };
+        let names: Vec<&str> = simple.assignments.iter().map(|assignment| assignment.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(simple.assignments[1].value, Word("\"x y\"".to_string()));
+        assert_eq!(simple.words, words(&["env", "c=2"]));
+    }
+
+    #[test]
+    fn arith_command_vs_nested_subshells() {
+        assert_eq!(command("((x++))"), Command::Arith(Word("x++".to_string())));
+        let inner = Command::Compound(
+            CompoundCommand::Subshell(parse_list("echo a")),
+            vec![redirect(1, RedirectKind::Write, "f")],
+        );
+        let mut expected = parse_list("x | cat");
+        expected.items[0].first.commands[0] = inner;
+        assert_eq!(command("((echo a)>f | cat)"), Command::Compound(CompoundCommand::Subshell(expected), vec![]));
+    }
+
+    #[test]
+    fn input_redirects() {
+        assert_eq!(command("cat 0<in"), simple(&["cat"], vec![redirect(0, RedirectKind::Read, "in")]));
+        assert_eq!(command("cat<in"), simple(&["cat"], vec![redirect(0, RedirectKind::Read, "in")]));
+        assert_eq!(command("cat <<<'a b'"), simple(&["cat"], vec![redirect(0, RedirectKind::HereString, "'a b'")]));
+    }
+
+    #[test]
+    fn here_doc_redirect() {
+        assert_eq!(
+            pipeline("cat <<EOF | wc -l\nhello\nEOF\n"),
+            vec![
+                simple(&["cat"], vec![redirect(0, RedirectKind::HereDoc { expand: true }, "hello\n")]),
+                simple(&["wc", "-l"], vec![]),
+            ]
+        );
+        assert_eq!(
+            command("cat 3<<'E'\n$x\nE"),
+            simple(&["cat"], vec![redirect(3, RedirectKind::HereDoc { expand: false }, "$x\n")])
+        );
+    }
+
+    #[test]
+    fn dup_redirects_in_order() {
+        assert_eq!(
+            command("cmd >out 2>&1"),
+            simple(&["cmd"], vec![redirect(1, RedirectKind::Write, "out"), redirect(2, RedirectKind::DupWrite, "1")])
+        );
+        assert_eq!(command("cmd 3<&-"), simple(&["cmd"], vec![redirect(3, RedirectKind::DupRead, "-")]));
+        assert_eq!(command("cmd &>>log"), simple(&["cmd"], vec![redirect(1, RedirectKind::AppendAll, "log")]));
+    }
+
+    #[test]
+    fn case_items() {
+        let Command::Compound(CompoundCommand::Case { word, items }, _) =
+            command("case $x in (a|b) echo ab;; c) ((n++)) ;& *) ;;& esac")
+        else {
+            panic!("not a case command");
+        };
+        assert_eq!(word, Word("$x".to_string()));
+        let patterns: Vec<Vec<Word>> = items.iter().map(|item| item.patterns.clone()).collect();
+        assert_eq!(patterns, vec![words(&["a", "b"]), words(&["c"]), words(&["*"])]);
+        let terminators: Vec<CaseTerminator> = items.iter().map(|item| item.terminator).collect();
+        assert_eq!(terminators, vec![CaseTerminator::Break, CaseTerminator::FallThrough, CaseTerminator::Continue]);
+        assert_eq!(items[1].body, parse_list("((n++))"));
+        assert!(items[2].body.items.is_empty());
+        assert_eq!(error("a ;; b"), ParseError::Unexpected(";;".to_string()));
+    }
+
+    #[test]
+    fn function_with_arith_body() {
+        let Command::FunctionDef { name, body } = command("f() { ((x=5)); echo \"x=$x\"; }") else {
+            panic!("not a function definition");
+        };
+        assert_eq!(name, "f");
+        assert_eq!(*body, Command::Compound(CompoundCommand::Group(parse_list("((x=5)); echo \"x=$x\"")), vec![]));
+    }
+
+    #[test]
+    fn aliases_expand_in_command_position() {
+        let aliases = BTreeMap::from([
+            ("ll".to_string(), "ls -l".to_string()),
+            ("sudo".to_string(), "sudo ".to_string()),
+        ]);
+        let parse_with_aliases = |input: &str| parse(input, &aliases, true).unwrap().items[0].first.commands.clone();
+        assert_eq!(parse_with_aliases("ll ll"), vec![simple(&["ls", "-l", "ll"], vec![])]);
+        assert_eq!(parse_with_aliases("echo ll"), vec![simple(&["echo", "ll"], vec![])]);
+        // a value ending in a blank makes the next word a candidate too
+        assert_eq!(parse_with_aliases("sudo ll"), vec![simple(&["sudo", "ls", "-l"], vec![])]);
+        assert_eq!(parse_with_aliases("'ll'"), vec![simple(&["'ll'"], vec![])]);
+    }
+
+    #[test]
+    fn incomplete_input() {
+        for incomplete in ["a |", "a &&", "if true; then", "for i in a", "echo 'a", "echo a \\", "cat <<E\nx"] {
+            assert!(error(incomplete).is_incomplete(), "{}", incomplete);
+        }
+        assert!(!error("a ;; b").is_incomplete());
+        assert!(!error("echo )").is_incomplete());
+    }
+}
@@ -0,0 +1,235 @@
+use crate::ast::{
+    AndOr, AndOrOp, CaseTerminator, Command, CompoundCommand, List, Pipeline, Redirect, RedirectKind, SimpleCommand,
+};
+
+/// Formats a function definition the way `type` shows it, with one command
+/// per line and nested bodies indented by four spaces.
+pub fn function_definition(name: &str, body: &Command) -> String {
+    let mut printer = Printer::default();
+    printer.write(&format!("{} () ", name));
+    printer.newline();
+    printer.command(body);
+    printer.out
+}
+
+/// Builds shell source from a syntax tree.
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+    at_line_start: bool,
+    /// Here-document bodies and delimiters to write out after the current line.
+    here_docs: Vec<(String, String)>,
+}
+
+impl Printer {
+    fn write(&mut self, text: &str) {
+        if self.at_line_start {
+            self.out.push_str(&"    ".repeat(self.indent));
+            self.at_line_start = false;
+        }
+        self.out.push_str(text);
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for (body, delimiter) in std::mem::take(&mut self.here_docs) {
+            self.out.push_str(&body);
+            self.out.push_str(&delimiter);
+            self.out.push('\n');
+        }
+        self.at_line_start = true;
+    }
+
+    /// Writes each command of `list` on its own line.
+    fn block(&mut self, list: &List) {
+        self.indent += 1;
+        for item in &list.items {
+            self.and_or(item);
+            self.newline();
+        }
+        self.indent -= 1;
+    }
+
+    /// Writes `list` on the current line, separated by `;`.
+    fn inline(&mut self, list: &List) {
+        for (i, item) in list.items.iter().enumerate() {
+            if i > 0 {
+                self.write("; ");
+            }
+            self.and_or(item);
+        }
+    }
+
+    fn and_or(&mut self, and_or: &AndOr) {
+        self.pipeline(&and_or.first);
+        for (op, pipeline) in &and_or.rest {
+            self.write(match op {
+                AndOrOp::And => " && ",
+                AndOrOp::Or => " || ",
+            });
+            self.pipeline(pipeline);
+        }
+    }
+
+    fn pipeline(&mut self, pipeline: &Pipeline) {
+        for (i, command) in pipeline.commands.iter().enumerate() {
+            if i > 0 {
+                self.write(" | ");
+            }
+            self.command(command);
+        }
+    }
+
+    fn command(&mut self, command: &Command) {
+        match command {
+            Command::Simple(simple) => self.simple(simple),
+            Command::Arith(expr) => self.write(&format!("(({}))", expr.0)),
+            Command::Compound(compound, redirects) => {
+                self.compound(compound);
+                self.redirects(redirects);
+            }
+            Command::FunctionDef { name, body } => {
+                self.write(&format!("{} () ", name));
+                self.newline();
+                self.command(body);
+            }
+        }
+    }
+
+    fn simple(&mut self, command: &SimpleCommand) {
+        let mut parts: Vec<String> =
+            command.assignments.iter().map(|assignment| format!("{}={}", assignment.name, assignment.value.0)).collect();
+        parts.extend(command.words.iter().map(|word| word.0.clone()));
+        self.write(&parts.join(" "));
+        if !parts.is_empty() && !command.redirects.is_empty() {
+            self.write(" ");
+        }
+        for (i, redirect) in command.redirects.iter().enumerate() {
+            if i > 0 {
+                self.write(" ");
+            }
+            self.redirect(redirect);
+        }
+    }
+
+    fn redirects(&mut self, redirects: &[Redirect]) {
+        for redirect in redirects {
+            self.write(" ");
+            self.redirect(redirect);
+        }
+    }
+
+    fn redirect(&mut self, redirect: &Redirect) {
+        let (op, default_fd) = match redirect.kind {
+            RedirectKind::Write => (">", 1),
+            RedirectKind::Append => (">>", 1),
+            RedirectKind::Read => ("<", 0),
+            RedirectKind::ReadWrite => ("<>", 0),
+            RedirectKind::DupRead => ("<&", 0),
+            RedirectKind::DupWrite => (">&", 1),
+            RedirectKind::WriteAll => ("&>", 1),
+            RedirectKind::AppendAll => ("&>>", 1),
+            RedirectKind::HereDoc { .. } => ("<<", 0),
+            RedirectKind::HereString => ("<<<", 0),
+        };
+        if redirect.fd != default_fd {
+            self.write(&redirect.fd.to_string());
+        }
+        match redirect.kind {
+            RedirectKind::HereDoc { expand } => {
+                let body = &redirect.target.0;
+                let mut delimiter = String::from("EOF");
+                while body.lines().any(|line| line == delimiter) {
+                    delimiter.push('_');
+                }
+                match expand {
+                    true => self.write(&format!("<<{}", delimiter)),
+                    false => self.write(&format!("<<'{}'", delimiter)),
+                }
+                self.here_docs.push((body.clone(), delimiter));
+            }
+            _ => self.write(&format!("{}{}", op, redirect.target.0)),
+        }
+    }
+
+    fn compound(&mut self, compound: &CompoundCommand) {
+        match compound {
+            CompoundCommand::Subshell(body) => {
+                self.write("( ");
+                self.newline();
+                self.block(body);
+                self.write(")");
+            }
+            CompoundCommand::Group(body) => {
+                self.write("{ ");
+                self.newline();
+                self.block(body);
+                self.write("}");
+            }
+            CompoundCommand::If { branches, else_body } => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    self.write(if i == 0 { "if " } else { "elif " });
+                    self.inline(condition);
+                    self.write("; then");
+                    self.newline();
+                    self.block(body);
+                }
+                if let Some(body) = else_body {
+                    self.write("else");
+                    self.newline();
+                    self.block(body);
+                }
+                self.write("fi");
+            }
+            CompoundCommand::While { condition, body, until } => {
+                self.write(if *until { "until " } else { "while " });
+                self.inline(condition);
+                self.write("; do");
+                self.newline();
+                self.block(body);
+                self.write("done");
+            }
+            CompoundCommand::For { name, words, body } => {
+                self.write(&format!("for {}", name));
+                if let Some(words) = words {
+                    self.write(" in");
+                    for word in words {
+                        self.write(&format!(" {}", word.0));
+                    }
+                }
+                self.write("; do");
+                self.newline();
+                self.block(body);
+                self.write("done");
+            }
+            CompoundCommand::ArithFor { init, condition, step, body } => {
+                self.write(&format!("for (({};{};{}))", init.0, condition.0, step.0));
+                self.newline();
+                self.write("do");
+                self.newline();
+                self.block(body);
+                self.write("done");
+            }
+            CompoundCommand::Case { word, items } => {
+                self.write(&format!("case {} in", word.0));
+                self.newline();
+                self.indent += 1;
+                for item in items {
+                    let patterns: Vec<&str> = item.patterns.iter().map(|pattern| pattern.0.as_str()).collect();
+                    self.write(&format!("{})", patterns.join(" | ")));
+                    self.newline();
+                    self.block(&item.body);
+                    self.write(match item.terminator {
+                        CaseTerminator::Break => ";;",
+                        CaseTerminator::FallThrough => ";&",
+                        CaseTerminator::Continue => ";;&",
+                    });
+                    self.newline();
+                }
+                self.indent -= 1;
+                self.write("esac");
+            }
+        }
+    }
+}
@@ -0,0 +1,738 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::process::{self, Command as Process};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::ast::{
+    AndOr, AndOrOp, Assignment, CaseItem, CaseTerminator, Command, CompoundCommand, List, Pipeline, Redirect, RedirectKind, SimpleCommand, Word,
+};
+use crate::builtins::BUILTINS;
+use crate::glob;
+use crate::line_buffer::LineBuffer;
+use crate::sys::{self, Fork};
+use crate::expand::ExpandError;
+use crate::variables::Variables;
+
+pub struct Shell {
+    pub line_reader: LineBuffer,
+    pub interactive: bool,
+    pub current_dir: PathBuf,
+    pub vars: Variables,
+    /// How many history entries `history -a` has already written out.
+    pub history_appended: usize,
+    /// Exit status of the most recent pipeline, as seen by `$?`.
+    pub last_status: i32,
+    /// Status of the last command substitution run while expanding the
+    /// current simple command, which becomes the status of a bare assignment.
+    pub substitution_status: Option<i32>,
+    /// Set by `exit`; the caller stops reading commands once it is `Some`.
+    pub exit_code: Option<i32>,
+    /// `$0`: the name the shell was started as.
+    pub arg0: String,
+    /// `$1`, `$2`, ... as set by the script arguments or `set --`.
+    pub positional: Vec<String>,
+    /// Functions by name. Bodies are shared so that a function can be
+    /// redefined while it is running.
+    pub functions: HashMap<String, Rc<Command>>,
+    /// Alias names and the text they stand for.
+    pub aliases: BTreeMap<String, String>,
+    /// How many function calls are in progress.
+    pub function_depth: usize,
+    /// How many files are being run by `source`.
+    pub source_depth: usize,
+    /// How many loops the command being run is nested in, within the current
+    /// function.
+    pub loop_depth: usize,
+    /// Set by `break`, `continue` and `return`; commands stop running until
+    /// the loop or function it targets picks it up.
+    pub control: Option<Control>,
+    /// The `interactive_comments` option: whether `#` starts a comment in
+    /// commands typed at the terminal.
+    pub interactive_comments: bool,
+    /// Whether `#` starts a comment in the commands being run, which is
+    /// always the case except at the terminal with `interactive_comments`
+    /// off. Command substitutions follow the command they are part of.
+    pub comments: bool,
+}
+
+/// A pending change of control flow. `Break` and `Continue` count the loops
+/// still to leave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Break(usize),
+    Continue(usize),
+    Return,
+}
+
+/// Descriptors replaced by a command's redirections, and the copies needed
+/// to put them back once the command is done.
+#[derive(Default)]
+struct SavedFds {
+    fds: Vec<(RawFd, Option<RawFd>)>,
+}
+
+/// What a redirect puts on the descriptors it replaces.
+#[derive(Clone, Copy)]
+enum RedirectSource {
+    /// A newly opened file, closed once it has been copied into place.
+    File(RawFd),
+    /// An existing descriptor, as in `2>&1`.
+    Dup(RawFd),
+    /// `N>&-`
+    Close,
+}
+
+impl Shell {
+    pub fn new(interactive: bool) -> Self {
+        let mut line_reader = LineBuffer::new();
+        line_reader.set_builtins(BUILTINS);
+        Self {
+            line_reader,
+            interactive,
+            current_dir: env::current_dir().unwrap(),
+            vars: Variables::from_env(),
+            history_appended: 0,
+            last_status: 0,
+            substitution_status: None,
+            exit_code: None,
+            arg0: String::new(),
+            positional: vec![],
+            functions: HashMap::new(),
+            aliases: BTreeMap::new(),
+            function_depth: 0,
+            source_depth: 0,
+            loop_depth: 0,
+            control: None,
+            interactive_comments: true,
+            comments: true,
+        }
+    }
+
+    /// Resolves `path` against the shell's working directory.
+    pub fn resolve_path(&self, path: &str) -> PathBuf {
+        self.current_dir.join(path)
+    }
+
+    pub fn execute(&mut self, list: &List) -> i32 {
+        let mut status = 0;
+        for and_or in &list.items {
+            if self.exit_code.is_some() || self.control.is_some() {
+                break;
+            }
+            status = self.exec_and_or(and_or);
+        }
+        status
+    }
+
+    fn exec_and_or(&mut self, and_or: &AndOr) -> i32 {
+        let mut status = self.exec_pipeline(&and_or.first);
+        self.last_status = status;
+        for (op, pipeline) in &and_or.rest {
+            if self.exit_code.is_some() || self.control.is_some() {
+                break;
+            }
+            let run = match op {
+                AndOrOp::And => status == 0,
+                AndOrOp::Or => status != 0,
+            };
+            if run {
+                status = self.exec_pipeline(pipeline);
+                self.last_status = status;
+            }
+        }
+        status
+    }
+
+    fn exec_pipeline(&mut self, pipeline: &Pipeline) -> i32 {
+        if let [command] = pipeline.commands.as_slice() {
+            return self.exec_command(command);
+        }
+        io::stdout().flush().unwrap();
+        let mut children = vec![];
+        let mut stdin = None;
+        let last = pipeline.commands.len() - 1;
+        for (i, command) in pipeline.commands.iter().enumerate() {
+            let (next_stdin, stdout) = if i < last {
+                match sys::pipe() {
+                    Ok((read, write)) => (Some(read), Some(write)),
+                    Err(err) => {
+                        eprintln!("pipe: {}", err);
+                        break;
+                    }
+                }
+            } else {
+                (None, None)
+            };
+            let forked = self.fork_subshell(|shell| {
+                if let Some(fd) = stdin {
+                    sys::dup2(fd, 0).unwrap();
+                    sys::close(fd);
+                }
+                if let Some(fd) = stdout {
+                    sys::dup2(fd, 1).unwrap();
+                    sys::close(fd);
+                }
+                if let Some(fd) = next_stdin {
+                    sys::close(fd);
+                }
+                shell.exec_command(command)
+            });
+            match forked {
+                Ok(pid) => children.push(pid),
+                Err(err) => eprintln!("fork: {}", err),
+            }
+            if let Some(fd) = stdin {
+                sys::close(fd);
+            }
+            if let Some(fd) = stdout {
+                sys::close(fd);
+            }
+            stdin = next_stdin;
+        }
+        if let Some(fd) = stdin {
+            sys::close(fd);
+        }
+        let mut status = 0;
+        for pid in children {
+            status = sys::wait_pid(pid);
+        }
+        status
+    }
+
+    /// Forks a copy of the shell that runs `body` and exits with the status it
+    /// returns. Nothing the child does is visible to the parent.
+    pub fn fork_subshell<F: FnOnce(&mut Shell) -> i32>(&mut self, body: F) -> io::Result<libc::pid_t> {
+        io::stdout().flush().ok();
+        match sys::fork()? {
+            Fork::Child => {
+                let status = body(self);
+                io::stdout().flush().ok();
+                sys::exit_now(self.exit_code.unwrap_or(status));
+            }
+            Fork::Parent(pid) => Ok(pid),
+        }
+    }
+
+    /// Runs `list` in a subshell and returns what it wrote to stdout along
+    /// with its exit status.
+    pub fn capture(&mut self, list: &List) -> io::Result<(Vec<u8>, i32)> {
+        let (read, write) = sys::pipe()?;
+        let forked = self.fork_subshell(|shell| {
+            sys::dup2(write, 1).unwrap();
+            sys::close(write);
+            sys::close(read);
+            shell.execute(list)
+        });
+        sys::close(write);
+        let mut reader = unsafe { File::from_raw_fd(read) };
+        let pid = forked?;
+        let mut output = vec![];
+        let result = reader.read_to_end(&mut output);
+        let status = sys::wait_pid(pid);
+        result?;
+        Ok((output, status))
+    }
+
+    fn exec_command(&mut self, command: &Command) -> i32 {
+        match command {
+            Command::Simple(simple) => self.exec_simple(simple),
+            Command::Arith(expr) => match self.eval_arith(&expr.0) {
+                Ok(value) => (value == 0) as i32,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    1
+                }
+            },
+            Command::FunctionDef { name, body } => {
+                self.functions.insert(name.clone(), body.clone());
+                0
+            }
+            Command::Compound(compound, redirects) => {
+                let saved = match self.apply_redirects(redirects) {
+                    Ok(saved) => saved,
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        return 1;
+                    }
+                };
+                let status = self.exec_compound(compound);
+                self.restore_fds(saved);
+                status
+            }
+        }
+    }
+
+    fn exec_compound(&mut self, compound: &CompoundCommand) -> i32 {
+        match compound {
+            CompoundCommand::Subshell(body) => match self.fork_subshell(|shell| shell.execute(body)) {
+                Ok(pid) => sys::wait_pid(pid),
+                Err(err) => {
+                    eprintln!("fork: {}", err);
+                    1
+                }
+            },
+            CompoundCommand::Group(body) => self.execute(body),
+            CompoundCommand::If { branches, else_body } => {
+                for (condition, body) in branches {
+                    let status = self.execute(condition);
+                    if self.exit_code.is_some() {
+                        return status;
+                    }
+                    if status == 0 {
+                        return self.execute(body);
+                    }
+                }
+                match else_body {
+                    Some(body) => self.execute(body),
+                    None => 0,
+                }
+            }
+            CompoundCommand::While { condition, body, until } => {
+                self.loop_depth += 1;
+                let mut status = 0;
+                loop {
+                    let condition_status = self.execute(condition);
+                    if self.end_iteration() || (condition_status == 0) == *until {
+                        break;
+                    }
+                    status = self.execute(body);
+                    if self.end_iteration() {
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+                status
+            }
+            CompoundCommand::For { name, words, body } => {
+                let values = match words {
+                    Some(words) => match self.expand_words(words) {
+                        Ok(values) => values,
+                        Err(err) => {
+                            eprintln!("{}", err);
+                            return 1;
+                        }
+                    },
+                    None => self.positional.clone(),
+                };
+                self.loop_depth += 1;
+                let mut status = 0;
+                for value in values {
+                    self.vars.set(name, value);
+                    status = self.execute(body);
+                    if self.end_iteration() {
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+                status
+            }
+            CompoundCommand::ArithFor { init, condition, step, body } => {
+                self.loop_depth += 1;
+                let result = self.exec_arith_for(init, condition, step, body);
+                self.loop_depth -= 1;
+                result.unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    1
+                })
+            }
+            CompoundCommand::Case { word, items } => self.exec_case(word, items).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                1
+            }),
+        }
+    }
+
+    fn exec_case(&mut self, word: &Word, items: &[CaseItem]) -> Result<i32, ExpandError> {
+        let word = self.expand_string(word)?;
+        let mut status = 0;
+        // set by `;&`: run the next body without testing its patterns
+        let mut fall_through = false;
+        for item in items {
+            if !fall_through && !self.case_matches(&word, &item.patterns)? {
+                continue;
+            }
+            status = self.execute(&item.body);
+            if self.exit_code.is_some() || self.control.is_some() {
+                break;
+            }
+            match item.terminator {
+                CaseTerminator::Break => break,
+                CaseTerminator::FallThrough => fall_through = true,
+                CaseTerminator::Continue => fall_through = false,
+            }
+        }
+        Ok(status)
+    }
+
+    fn case_matches(&mut self, word: &str, patterns: &[Word]) -> Result<bool, ExpandError> {
+        for pattern in patterns {
+            if glob::matches(&self.expand_pattern(pattern)?, word) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn exec_arith_for(&mut self, init: &Word, condition: &Word, step: &Word, body: &List) -> Result<i32, ExpandError> {
+        let mut status = 0;
+        self.eval_for_expr(init)?;
+        while self.eval_for_expr(condition)? != 0 {
+            status = self.execute(body);
+            if self.end_iteration() {
+                break;
+            }
+            self.eval_for_expr(step)?;
+        }
+        Ok(status)
+    }
+
+    /// Evaluates one of the expressions of `for (( ... ))`, where an empty
+    /// one counts as true.
+    fn eval_for_expr(&mut self, expr: &Word) -> Result<i64, ExpandError> {
+        if expr.0.trim().is_empty() {
+            return Ok(1);
+        }
+        self.eval_arith(&expr.0)
+    }
+
+    /// Picks up a `break` or `continue` aimed at the innermost loop. Returns
+    /// whether that loop should stop.
+    fn end_iteration(&mut self) -> bool {
+        match self.control.take() {
+            None => self.exit_code.is_some(),
+            Some(Control::Return) => {
+                self.control = Some(Control::Return);
+                true
+            }
+            Some(Control::Break(count)) => {
+                if count > 1 {
+                    self.control = Some(Control::Break(count - 1));
+                }
+                true
+            }
+            Some(Control::Continue(count)) => {
+                if count > 1 {
+                    self.control = Some(Control::Continue(count - 1));
+                }
+                count > 1
+            }
+        }
+    }
+
+    fn exec_simple(&mut self, command: &SimpleCommand) -> i32 {
+        self.substitution_status = None;
+        let args = match self.expand_command_words(&command.words) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!("{}", err);
+                return 1;
+            }
+        };
+        let saved = match self.apply_redirects(&command.redirects) {
+            Ok(saved) => saved,
+            Err(err) => {
+                eprintln!("{}", err);
+                return 1;
+            }
+        };
+        let status = if args.is_empty() {
+            match self.assign(&command.assignments) {
+                0 => self.substitution_status.unwrap_or(0),
+                status => status,
+            }
+        } else {
+            match self.assign_temporarily(&command.assignments) {
+                Ok(()) => {
+                    let status = self.run(&args);
+                    if !command.assignments.is_empty() {
+                        self.vars.pop_scope();
+                    }
+                    status
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    1
+                }
+            }
+        };
+        self.restore_fds(saved);
+        status
+    }
+
+    /// Runs a builtin or external command with already expanded arguments.
+    fn run(&mut self, args: &[String]) -> i32 {
+        if let Some(body) = self.functions.get(&args[0]).cloned() {
+            self.call_function(&body, &args[1..])
+        } else if BUILTINS.contains(&args[0].as_str()) {
+            let status = self.run_builtin(args);
+            io::stdout().flush().ok();
+            status
+        } else {
+            let env = self.vars.exported();
+            self.run_external(args, &env)
+        }
+    }
+
+    /// Runs a function body with `args` as its positional parameters.
+    fn call_function(&mut self, body: &Command, args: &[String]) -> i32 {
+        let saved_positional = std::mem::replace(&mut self.positional, args.to_vec());
+        let saved_loop_depth = std::mem::take(&mut self.loop_depth);
+        self.function_depth += 1;
+        self.vars.push_scope();
+        let status = self.exec_command(body);
+        self.vars.pop_scope();
+        self.function_depth -= 1;
+        self.loop_depth = saved_loop_depth;
+        self.positional = saved_positional;
+        if self.control == Some(Control::Return) {
+            self.control = None;
+        }
+        status
+    }
+
+    /// Performs `name=value` assignments in the current shell.
+    fn assign(&mut self, assignments: &[Assignment]) -> i32 {
+        for assignment in assignments {
+            match self.expand_assignment(&assignment.value) {
+                Ok(value) => self.vars.set(&assignment.name, value),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return 1;
+                }
+            }
+        }
+        0
+    }
+
+    /// Exports `name=value` prefixes for the duration of one command, in a
+    /// scope of their own that the caller pops once the command is done.
+    /// Without prefixes, no scope is pushed, so that `local` still reaches
+    /// the function's scope.
+    fn assign_temporarily(&mut self, assignments: &[Assignment]) -> Result<(), ExpandError> {
+        if assignments.is_empty() {
+            return Ok(());
+        }
+        self.vars.push_scope();
+        for assignment in assignments {
+            match self.expand_assignment(&assignment.value) {
+                Ok(value) => self.vars.set_local(&assignment.name, Some(value), true),
+                Err(err) => {
+                    self.vars.pop_scope();
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The directories listed in `$PATH`.
+    pub fn path(&self) -> String {
+        self.vars.get("PATH").unwrap_or_default()
+    }
+
+    /// Spawns an external command with the given environment and waits for it.
+    pub fn run_external(&mut self, args: &[String], env: &[(String, String)]) -> i32 {
+        let command = &args[0];
+        let executable_path = if command.contains('/') {
+            Some(self.resolve_path(command))
+        } else {
+            find_executable(command, &self.path()).map(PathBuf::from)
+        };
+        let Some(executable_path) = executable_path else {
+            eprintln!("{}: command not found", command);
+            return 127;
+        };
+        let spawned = Process::new(&executable_path)
+            .arg0(command)
+            .args(&args[1..])
+            .env_clear()
+            .envs(env.iter().map(|(name, value)| (name, value)))
+            .current_dir(&self.current_dir)
+            .spawn();
+        match spawned {
+            Ok(mut program) => match program.wait() {
+                Ok(status) => exit_status(status),
+                Err(_) => 1,
+            },
+            Err(err) => {
+                eprintln!("{}: {}", command, io_error_message(&err));
+                match err.kind() {
+                    io::ErrorKind::NotFound => 127,
+                    _ => 126,
+                }
+            }
+        }
+    }
+
+    /// Applies redirections to the shell's own descriptors, in order. The
+    /// returned copies let `restore_fds` undo them afterwards.
+    fn apply_redirects(&mut self, redirects: &[Redirect]) -> Result<SavedFds, String> {
+        let mut saved = SavedFds::default();
+        for redirect in redirects {
+            let (fds, source) = match self.open_redirect(redirect) {
+                Ok(opened) => opened,
+                Err(err) => {
+                    self.restore_fds(saved);
+                    return Err(err);
+                }
+            };
+            io::stdout().flush().ok();
+            let mut result = Ok(());
+            for &fd in &fds {
+                if !saved.fds.iter().any(|(saved_fd, _)| *saved_fd == fd) {
+                    saved.fds.push((fd, sys::save_fd(fd)));
+                }
+                result = result.and_then(|_| match source {
+                    RedirectSource::File(src) | RedirectSource::Dup(src) => sys::dup2(src, fd),
+                    RedirectSource::Close => {
+                        sys::close(fd);
+                        Ok(())
+                    }
+                });
+            }
+            if let RedirectSource::File(src) = source {
+                // a file may have landed on the very descriptor it was meant for
+                if !fds.contains(&src) {
+                    sys::close(src);
+                }
+            }
+            if let Err(err) = result {
+                self.restore_fds(saved);
+                return Err(format!("{}: {}", redirect.fd, io_error_message(&err)));
+            }
+        }
+        Ok(saved)
+    }
+
+    /// Opens the file or text a redirect points at. Returns the descriptors
+    /// it replaces and what goes on them.
+    fn open_redirect(&mut self, redirect: &Redirect) -> Result<(Vec<RawFd>, RedirectSource), String> {
+        let content = match redirect.kind {
+            RedirectKind::HereDoc { expand: false } => Some(redirect.target.0.clone()),
+            RedirectKind::HereDoc { expand: true } => {
+                Some(self.expand_here_doc(&redirect.target.0).map_err(|err| err.to_string())?)
+            }
+            RedirectKind::HereString => {
+                let word = self.expand_string(&redirect.target).map_err(|err| err.to_string())?;
+                Some(word + "\n")
+            }
+            _ => None,
+        };
+        if let Some(content) = content {
+            let fd = here_doc_fd(&content)
+                .map_err(|err| format!("cannot create temp file for here-document: {}", io_error_message(&err)))?;
+            return Ok((vec![redirect.fd], RedirectSource::File(fd)));
+        }
+        let target = self.expand_word(&redirect.target).map_err(|err| err.to_string())?;
+        let [target] = target.as_slice() else {
+            return Err(format!("{}: ambiguous redirect", redirect.target.0));
+        };
+        let mut kind = redirect.kind;
+        if kind == RedirectKind::DupRead || kind == RedirectKind::DupWrite {
+            if target == "-" {
+                return Ok((vec![redirect.fd], RedirectSource::Close));
+            }
+            if let Ok(src) = target.parse::<RawFd>() {
+                if !sys::is_open(src) {
+                    return Err(format!("{}: Bad file descriptor", src));
+                }
+                return Ok((vec![redirect.fd], RedirectSource::Dup(src)));
+            }
+            if kind == RedirectKind::DupRead || redirect.fd != 1 {
+                return Err(format!("{}: ambiguous redirect", redirect.target.0));
+            }
+            kind = RedirectKind::WriteAll;
+        }
+        let mut options = OpenOptions::new();
+        match kind {
+            RedirectKind::Read => options.read(true),
+            RedirectKind::ReadWrite => options.read(true).write(true).create(true),
+            RedirectKind::Append | RedirectKind::AppendAll => options.create(true).append(true),
+            _ => options.create(true).write(true).truncate(true),
+        };
+        let fd = match options.open(self.resolve_path(target)) {
+            Ok(file) => file.into_raw_fd(),
+            Err(err) => return Err(format!("{}: {}", target, io_error_message(&err))),
+        };
+        let fds = match kind {
+            RedirectKind::WriteAll | RedirectKind::AppendAll => vec![1, 2],
+            _ => vec![redirect.fd],
+        };
+        Ok((fds, RedirectSource::File(fd)))
+    }
+
+    fn restore_fds(&mut self, saved: SavedFds) {
+        io::stdout().flush().ok();
+        for (fd, copy) in saved.fds.into_iter().rev() {
+            match copy {
+                Some(copy) => {
+                    sys::dup2(copy, fd).ok();
+                    sys::close(copy);
+                }
+                None => sys::close(fd),
+            }
+        }
+    }
+}
+
+/// Turns an `io::Error` into the short message shells print, without the
+/// "(os error N)" suffix.
+pub fn io_error_message(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.find(" (os error") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
+fn exit_status(status: std::process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    match status.code() {
+        Some(code) => code,
+        None => 128 + status.signal().unwrap_or(0),
+    }
+}
+
+/// Writes here-document text to an unlinked temporary file and returns an fd
+/// positioned at its start.
+fn here_doc_fd(content: &str) -> io::Result<RawFd> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = env::temp_dir().join(format!("sh-heredoc-{}-{}", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+    fs::remove_file(&path)?;
+    file.write_all(content.as_bytes())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file.into_raw_fd())
+}
+
+pub fn find_executable(executable_name: &str, path_var: &str) -> Option<String> {
+    for dir_name in path_var.split(":") {
+        let dir_path = PathBuf::from(dir_name);
+        if !dir_path.exists() {
+            continue;
+        }
+        let exec_path = dir_path.join(executable_name);
+        if !is_executable(&exec_path) {
+            continue;
+        }
+        return Some(String::from(exec_path.to_str().unwrap()));
+    }
+    None
+}
+
+fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let mode: u16 = metadata.permissions().mode() as u16;
+            let executable: u16 = 493u16;
+            metadata.is_file() && (mode & executable) == executable
+        }
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,135 @@
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::io::RawFd;
+
+/// First descriptor used when the shell stashes a copy of an fd it is about
+/// to redirect, so the copies stay out of the way of user-visible fds.
+const SAVED_FD_BASE: RawFd = 10;
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Creates a pipe whose ends are closed on exec; returns `(read, write)`.
+pub fn pipe() -> io::Result<(RawFd, RawFd)> {
+    let mut fds = [0; 2];
+    check(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) })?;
+    Ok((fds[0], fds[1]))
+}
+
+/// Makes `dst` a copy of `src` that stays open across exec. When they are
+/// the same descriptor, only its close-on-exec flag is cleared.
+pub fn dup2(src: RawFd, dst: RawFd) -> io::Result<()> {
+    if src == dst {
+        check(unsafe { libc::fcntl(dst, libc::F_SETFD, 0) })?;
+    } else {
+        check(unsafe { libc::dup2(src, dst) })?;
+    }
+    Ok(())
+}
+
+pub fn close(fd: RawFd) {
+    unsafe {
+        libc::close(fd);
+    }
+}
+
+pub fn is_open(fd: RawFd) -> bool {
+    fd >= 0 && unsafe { libc::fcntl(fd, libc::F_GETFD) } >= 0
+}
+
+/// Duplicates `fd` to a close-on-exec descriptor above the user range.
+/// Returns `None` if `fd` is not open.
+pub fn save_fd(fd: RawFd) -> Option<RawFd> {
+    let ret = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, SAVED_FD_BASE) };
+    if ret < 0 {
+        None
+    } else {
+        Some(ret)
+    }
+}
+
+/// Reads a single byte from `fd`, so nothing past it is consumed on behalf
+/// of later commands. Returns `None` at end of file.
+pub fn read_byte(fd: RawFd) -> io::Result<Option<u8>> {
+    let mut byte = 0u8;
+    loop {
+        let ret = unsafe { libc::read(fd, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+        match ret {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+pub enum Fork {
+    Parent(libc::pid_t),
+    Child,
+}
+
+pub fn fork() -> io::Result<Fork> {
+    match check(unsafe { libc::fork() })? {
+        0 => {
+            // the runtime ignores SIGPIPE; children should die like any other program
+            unsafe {
+                libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+            }
+            Ok(Fork::Child)
+        }
+        pid => Ok(Fork::Parent(pid)),
+    }
+}
+
+/// Waits for `pid` and converts its wait status into a shell exit status.
+pub fn wait_pid(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+    loop {
+        let ret = unsafe { libc::waitpid(pid, &mut status, 0) };
+        if ret >= 0 {
+            break;
+        }
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return 127;
+        }
+    }
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        1
+    }
+}
+
+/// Leaves the process immediately, skipping Rust's exit handlers. Used by
+/// forked children so they never touch state owned by the parent shell.
+pub fn exit_now(code: i32) -> ! {
+    unsafe { libc::_exit(code) }
+}
+
+/// Looks up a home directory in the passwd database: `user`'s, or the
+/// current user's if `None`.
+pub fn home_dir(user: Option<&str>) -> Option<String> {
+    let entry = match user {
+        Some(user) => {
+            let name = CString::new(user).ok()?;
+            unsafe { libc::getpwnam(name.as_ptr()) }
+        }
+        None => unsafe { libc::getpwuid(libc::getuid()) },
+    };
+    if entry.is_null() {
+        return None;
+    }
+    let dir = unsafe { CStr::from_ptr((*entry).pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
+}
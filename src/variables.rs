@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::env;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Variable {
+    /// `None` for a name that was exported before it was given a value.
+    pub value: Option<String>,
+    pub exported: bool,
+}
+
+/// The shell's variable table: a stack of scopes, with the global scope at
+/// the bottom. Lookups see the innermost scope that has the name, so a
+/// function's locals hide the caller's variables until it returns.
+/// Exported variables make up the environment handed to external commands.
+#[derive(Debug, Clone)]
+pub struct Variables {
+    scopes: Vec<HashMap<String, Variable>>,
+}
+
+impl Default for Variables {
+    fn default() -> Self {
+        Self { scopes: vec![HashMap::new()] }
+    }
+}
+
+impl Variables {
+    /// Builds the table from the process environment, with every variable
+    /// exported.
+    pub fn from_env() -> Self {
+        let mut vars = Self::default();
+        for (name, value) in env::vars() {
+            vars.scopes[0].insert(name, Variable { value: Some(value), exported: true });
+        }
+        vars
+    }
+
+    /// The variable `name` resolves to, which may be a local that hides an
+    /// outer one while being unset itself.
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Variable> {
+        self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name))
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).and_then(|var| var.value.clone())
+    }
+
+    /// Sets a variable in the innermost scope that has it, or globally,
+    /// keeping its export flag if it already exists.
+    pub fn set(&mut self, name: &str, value: String) {
+        match self.lookup_mut(name) {
+            Some(var) => var.value = Some(value),
+            None => {
+                self.scopes[0].insert(name.to_string(), Variable { value: Some(value), exported: false });
+            }
+        }
+    }
+
+    pub fn export(&mut self, name: &str) {
+        match self.lookup_mut(name) {
+            Some(var) => var.exported = true,
+            None => {
+                self.scopes[0].insert(name.to_string(), Variable { value: None, exported: true });
+            }
+        }
+    }
+
+    /// Unsets the variable `name` resolves to. A local of the innermost
+    /// function stays local, just without a value; anything further out is
+    /// removed, uncovering whatever it was hiding.
+    pub fn unset(&mut self, name: &str) {
+        let top = self.scopes.len() - 1;
+        let Some(depth) = self.scopes.iter().rposition(|scope| scope.contains_key(name)) else {
+            return;
+        };
+        if depth == top && depth > 0 {
+            self.scopes[depth].insert(name.to_string(), Variable::default());
+        } else {
+            self.scopes[depth].remove(name);
+        }
+    }
+
+    /// Starts a scope for a function call or for the temporary assignments
+    /// in front of a command.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    /// Declares `name` in the innermost scope. A `None` value keeps the
+    /// value it already has there, if any.
+    pub fn set_local(&mut self, name: &str, value: Option<String>, exported: bool) {
+        let scope = self.scopes.last_mut().unwrap();
+        let var = scope.entry(name.to_string()).or_default();
+        if value.is_some() {
+            var.value = value;
+        }
+        var.exported |= exported;
+    }
+
+    /// Every variable that has a value and is not hidden by a local, sorted
+    /// by name, with its export flag.
+    fn visible(&self) -> Vec<(String, String, bool)> {
+        let mut visible: HashMap<&String, &Variable> = HashMap::new();
+        for scope in &self.scopes {
+            visible.extend(scope);
+        }
+        let mut vars: Vec<(String, String, bool)> = visible
+            .into_iter()
+            .filter_map(|(name, var)| Some((name.clone(), var.value.clone()?, var.exported)))
+            .collect();
+        vars.sort();
+        vars
+    }
+
+    /// The variables that have a value, sorted by name.
+    pub fn all(&self) -> Vec<(String, String)> {
+        self.visible().into_iter().map(|(name, value, _)| (name, value)).collect()
+    }
+
+    /// The exported variables that have a value, sorted by name.
+    pub fn exported(&self) -> Vec<(String, String)> {
+        self.visible().into_iter().filter(|(_, _, exported)| *exported).map(|(name, value, _)| (name, value)).collect()
+    }
+}
+
+/// Whether `s` is a valid variable name: a letter or underscore followed by
+/// letters, digits and underscores.
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}